@@ -3,9 +3,6 @@ extern crate portmidi as pm;
 extern crate rustc_serialize;
 extern crate docopt;
 
-use std::time::Duration;
-use std::thread;
-
 const USAGE: &'static str = r#"
 portmidi-rs: monitor-device example
 
@@ -32,7 +29,6 @@ fn print_devices(pm: &pm::PortMidi) {
 fn main() {
     // initialize the PortMidi context.
     let context = pm::PortMidi::new().unwrap();
-    let timeout = Duration::from_millis(10);
 
     // setup the command line interface
     let args: Args = docopt::Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|err| {
@@ -45,14 +41,10 @@ fn main() {
     println!("Listening on: {}) {}", info.id(), info.name());
 
     // get the device's input port
-    let in_port = context.input_port(info, 1024).unwrap();
-
-    while let Ok(_) = in_port.poll() {
-        if let Ok(Some(event)) = in_port.read_n(1024) {
-            println!("{:?}", event);
-        }
-        // there is no blocking receive method in PortMidi, therefore
-        // we have to sleep some time to prevent a busy-wait loop
-        thread::sleep(timeout);
+    let mut in_port = context.input_port(info, 1024).unwrap();
+
+    // waits for the next event without busy-polling the device
+    for event in in_port.events().filter_map(|res| res.ok()) {
+        println!("{:?}", event);
     }
 }
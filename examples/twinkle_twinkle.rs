@@ -3,7 +3,6 @@ extern crate portmidi as pm;
 extern crate docopt;
 extern crate rustc_serialize;
 
-use std::thread;
 use std::time::Duration;
 
 use pm::MidiMessage;
@@ -99,7 +98,10 @@ fn main() {
 }
 
 fn play(mut out_port: pm::OutputPort, verbose: bool) -> pm::Result<()> {
-    for &(note, dur) in MELODY.iter().cycle() {
+    // schedule the melody once and let the player loop it, so the timing does not drift
+    let mut events = Vec::new();
+    let mut time = 0;
+    for &(note, dur) in MELODY.iter() {
         let note_on = MidiMessage {
             status: 0x90 + CHANNEL,
             data1: note,
@@ -109,9 +111,12 @@ fn play(mut out_port: pm::OutputPort, verbose: bool) -> pm::Result<()> {
         if verbose {
             println!("{}", note_on)
         }
-        out_port.write_message(note_on)?;
+        events.push(pm::MidiEvent {
+            message: note_on,
+            timestamp: time,
+        });
         // note hold time before sending note off
-        thread::sleep(Duration::from_millis(dur as u64 * 400));
+        time += dur * 400;
 
         let note_off = MidiMessage {
             status: 0x80 + CHANNEL,
@@ -122,9 +127,14 @@ fn play(mut out_port: pm::OutputPort, verbose: bool) -> pm::Result<()> {
         if verbose {
             println!("{}", note_off);
         }
-        out_port.write_message(note_off)?;
+        events.push(pm::MidiEvent {
+            message: note_off,
+            timestamp: time,
+        });
         // short pause
-        thread::sleep(Duration::from_millis(100));
+        time += 100;
     }
-    Ok(())
+    let player = pm::Player::from_events(&events);
+    player.set_loop(Some(Duration::from_millis(0)..Duration::from_millis(u64::from(time))));
+    player.play(&mut out_port)
 }
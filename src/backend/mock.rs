@@ -0,0 +1,364 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use backend::{Backend, BackendDevice, StreamId};
+use ffi;
+use time::TimeSource;
+use types::{Error, MidiEvent, MidiMessage, PortMidiDeviceId, Result};
+
+const MOCK_INTERFACE: &str = "Mock";
+
+struct MockDevice {
+    info: BackendDevice,
+    input: VecDeque<MidiEvent>,
+    output: Vec<MidiEvent>,
+    connections: Vec<PortMidiDeviceId>,
+    stream: Option<StreamId>,
+    // the time source and latency of the open stream
+    time_source: Option<Arc<dyn TimeSource>>,
+    latency: u32,
+    host_error: bool,
+}
+impl MockDevice {
+    /// Queues an input event, timestamped by the time source of the stream if there is one.
+    fn receive(&mut self, mut event: MidiEvent) {
+        if let Some(ref time_source) = self.time_source {
+            event.timestamp = time_source.now();
+        }
+        self.input.push_back(event);
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    devices: Vec<MockDevice>,
+    streams: HashMap<StreamId, PortMidiDeviceId>,
+    next_stream: usize,
+}
+impl MockState {
+    fn device_mut(&mut self, id: PortMidiDeviceId) -> Result<&mut MockDevice> {
+        if id < 0 {
+            return Err(Error::PortMidi(ffi::PmError::PmInvalidDeviceId));
+        }
+        self.devices
+            .get_mut(id as usize)
+            .ok_or(Error::PortMidi(ffi::PmError::PmInvalidDeviceId))
+    }
+
+    fn stream_device(&self, stream: StreamId) -> Result<PortMidiDeviceId> {
+        self.streams
+            .get(&stream)
+            .cloned()
+            .ok_or(Error::PortMidi(ffi::PmError::PmBadPtr))
+    }
+
+    fn open(
+        &mut self,
+        id: PortMidiDeviceId,
+        input: bool,
+        latency: u32,
+        time_source: Option<Arc<dyn TimeSource>>,
+    ) -> Result<StreamId> {
+        let stream = StreamId(self.next_stream);
+        {
+            let device = self.device_mut(id)?;
+            let supported = if input {
+                device.info.input
+            } else {
+                device.info.output
+            };
+            if !supported || device.stream.is_some() {
+                return Err(Error::PortMidi(ffi::PmError::PmInvalidDeviceId));
+            }
+            device.stream = Some(stream);
+            device.time_source = time_source;
+            device.latency = latency;
+        }
+        self.next_stream += 1;
+        self.streams.insert(stream, id);
+        Ok(stream)
+    }
+
+    /// Records written events and forwards them to the connected input devices.
+    fn output(&mut self, stream: StreamId, events: &[MidiEvent]) -> Result<()> {
+        let id = self.stream_device(stream)?;
+        let (events, connections) = {
+            let device = self.device_mut(id)?;
+            let mut events = events.to_vec();
+            // like PortMidi, a timestamp of `0` means now if the stream has a latency
+            if let (Some(ref time_source), true) = (&device.time_source, device.latency > 0) {
+                let now = time_source.now();
+                for event in events.iter_mut().filter(|event| event.timestamp == 0) {
+                    event.timestamp = now;
+                }
+            }
+            device.output.extend_from_slice(&events);
+            (events, device.connections.clone())
+        };
+        for input in connections {
+            let device = self.device_mut(input)?;
+            for &event in &events {
+                device.receive(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An in-memory `Backend` for tests.
+///
+/// Devices are added with `add_input`, `add_output` or `add_device`, events for input devices are
+/// injected with `push_input` and the events written to output devices can be inspected
+/// with `take_output`. Output devices can be connected to input devices to loop back
+/// the written events.
+///
+/// The backend is cheaply clonable and all clones share the same devices, so a test can
+/// keep a clone after moving the backend into a `PortMidi` context.
+/// Input events of ports opened with a `TimeSource` are timestamped with it when they arrive,
+/// events written with a timestamp of `0` to output ports with a latency and a `TimeSource`
+/// are recorded with its current time. Otherwise timestamps are passed through unchanged.
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    state: Arc<Mutex<MockState>>,
+}
+impl MockBackend {
+    /// Creates a backend without any devices.
+    pub fn new() -> Self {
+        MockBackend::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        // ports use the state in `Drop`, so a panic elsewhere must not poison it for them
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Adds a device and returns its id, the `opened` flag of the description is ignored.
+    pub fn add_device(&self, device: BackendDevice) -> PortMidiDeviceId {
+        let mut state = self.state();
+        state.devices.push(MockDevice {
+            info: device,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            connections: Vec::new(),
+            stream: None,
+            time_source: None,
+            latency: 0,
+            host_error: false,
+        });
+        (state.devices.len() - 1) as PortMidiDeviceId
+    }
+
+    /// Adds an input device with the given name and returns its id.
+    pub fn add_input(&self, name: &str) -> PortMidiDeviceId {
+        self.add_device(BackendDevice {
+            name: name.to_owned(),
+            interface: MOCK_INTERFACE.to_owned(),
+            input: true,
+            output: false,
+            opened: false,
+        })
+    }
+
+    /// Adds an output device with the given name and returns its id.
+    pub fn add_output(&self, name: &str) -> PortMidiDeviceId {
+        self.add_device(BackendDevice {
+            name: name.to_owned(),
+            interface: MOCK_INTERFACE.to_owned(),
+            input: false,
+            output: true,
+            opened: false,
+        })
+    }
+
+    /// Removes a device, e.g. to simulate unplugging it.
+    /// The ids of all following devices are decremented, like PortMidi reassigns ids
+    /// when devices are enumerated again.
+    pub fn remove_device(&self, id: PortMidiDeviceId) -> Result<()> {
+        let mut state = self.state();
+        state.device_mut(id)?;
+        state.devices.remove(id as usize);
+        for device in &mut state.devices {
+            device.connections.retain(|&c| c != id);
+            for c in &mut device.connections {
+                if *c > id {
+                    *c -= 1;
+                }
+            }
+        }
+        // streams of the removed device become invalid
+        state.streams.retain(|_, device| *device != id);
+        for device in state.streams.values_mut() {
+            if *device > id {
+                *device -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forwards all events written to the `output` device to the `input` device.
+    pub fn connect(&self, output: PortMidiDeviceId, input: PortMidiDeviceId) -> Result<()> {
+        let mut state = self.state();
+        state.device_mut(input)?;
+        state.device_mut(output)?.connections.push(input);
+        Ok(())
+    }
+
+    /// Queues an event to be read from the given input device.
+    pub fn push_input<T: Into<MidiEvent>>(&self, id: PortMidiDeviceId, event: T) -> Result<()> {
+        self.state().device_mut(id)?.receive(event.into());
+        Ok(())
+    }
+
+    /// Returns and clears the events written to the given output device so far.
+    ///
+    /// SysEx messages are recorded the way PortMidi transmits them, packed into
+    /// events of four bytes each.
+    pub fn take_output(&self, id: PortMidiDeviceId) -> Result<Vec<MidiEvent>> {
+        let mut state = self.state();
+        let device = state.device_mut(id)?;
+        Ok(device.output.drain(..).collect())
+    }
+
+    /// Sets whether streams of the given device report a host error.
+    pub fn set_host_error(&self, id: PortMidiDeviceId, host_error: bool) -> Result<()> {
+        self.state().device_mut(id)?.host_error = host_error;
+        Ok(())
+    }
+
+    /// Returns `true` if a stream is open for the given device.
+    pub fn is_open(&self, id: PortMidiDeviceId) -> bool {
+        self.state()
+            .device_mut(id)
+            .map(|device| device.stream.is_some())
+            .unwrap_or(false)
+    }
+}
+impl Backend for MockBackend {
+    fn initialize(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn terminate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn count_devices(&self) -> Result<u32> {
+        Ok(self.state().devices.len() as u32)
+    }
+
+    fn device(&self, id: PortMidiDeviceId) -> Option<BackendDevice> {
+        self.state().device_mut(id).ok().map(|d| BackendDevice {
+            opened: d.stream.is_some(),
+            ..d.info.clone()
+        })
+    }
+
+    fn default_input_device_id(&self) -> Option<PortMidiDeviceId> {
+        self.state()
+            .devices
+            .iter()
+            .position(|d| d.info.input)
+            .map(|id| id as PortMidiDeviceId)
+    }
+
+    fn default_output_device_id(&self) -> Option<PortMidiDeviceId> {
+        self.state()
+            .devices
+            .iter()
+            .position(|d| d.info.output)
+            .map(|id| id as PortMidiDeviceId)
+    }
+
+    fn open_input(
+        &self,
+        id: PortMidiDeviceId,
+        _buffer_size: usize,
+        time_source: Option<Arc<dyn TimeSource>>,
+    ) -> Result<StreamId> {
+        self.state().open(id, true, 0, time_source)
+    }
+
+    fn open_output(
+        &self,
+        id: PortMidiDeviceId,
+        _buffer_size: usize,
+        latency: u32,
+        time_source: Option<Arc<dyn TimeSource>>,
+    ) -> Result<StreamId> {
+        self.state().open(id, false, latency, time_source)
+    }
+
+    unsafe fn read(&self, stream: StreamId, buffer: &mut [MidiEvent]) -> Result<usize> {
+        let mut state = self.state();
+        let id = state.stream_device(stream)?;
+        let input = &mut state.device_mut(id)?.input;
+        let cnt = buffer.len().min(input.len());
+        for (slot, event) in buffer.iter_mut().zip(input.drain(..cnt)) {
+            *slot = event;
+        }
+        Ok(cnt)
+    }
+
+    unsafe fn poll(&self, stream: StreamId) -> Result<bool> {
+        let mut state = self.state();
+        let id = state.stream_device(stream)?;
+        Ok(!state.device_mut(id)?.input.is_empty())
+    }
+
+    unsafe fn write(&self, stream: StreamId, events: &[MidiEvent]) -> Result<()> {
+        self.state().output(stream, events)
+    }
+
+    unsafe fn write_short(
+        &self,
+        stream: StreamId,
+        timestamp: ffi::PmTimestamp,
+        message: MidiMessage,
+    ) -> Result<()> {
+        self.state()
+            .output(stream, &[MidiEvent { message, timestamp }])
+    }
+
+    unsafe fn write_sysex(
+        &self,
+        stream: StreamId,
+        timestamp: ffi::PmTimestamp,
+        msg: &[u8],
+    ) -> Result<()> {
+        let events = msg
+            .chunks(4)
+            .map(|chunk| {
+                let mut raw = [0; 4];
+                raw[..chunk.len()].copy_from_slice(chunk);
+                MidiEvent {
+                    message: MidiMessage::from(raw),
+                    timestamp,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.state().output(stream, &events)
+    }
+
+    /// Written events are recorded immediately, so there is nothing to discard.
+    unsafe fn abort(&self, stream: StreamId) -> Result<()> {
+        self.state().stream_device(stream).map(|_| ())
+    }
+
+    unsafe fn has_host_error(&self, stream: StreamId) -> bool {
+        let mut state = self.state();
+        match state.stream_device(stream) {
+            Ok(id) => state.device_mut(id).map(|d| d.host_error).unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    unsafe fn close(&self, stream: StreamId) -> Result<()> {
+        let mut state = self.state();
+        let id = state.stream_device(stream)?;
+        state.streams.remove(&stream);
+        let device = state.device_mut(id)?;
+        device.stream = None;
+        device.time_source = None;
+        Ok(())
+    }
+}
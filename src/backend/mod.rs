@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use ffi;
+use time::TimeSource;
+use types::{MidiEvent, MidiMessage, PortMidiDeviceId, Result};
+
+mod mock;
+mod portmidi;
+pub use self::mock::*;
+pub use self::portmidi::*;
+
+/// Identifies a stream opened by a `Backend`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StreamId(pub(crate) usize);
+impl StreamId {
+    /// Creates a `StreamId` from a value chosen by a `Backend` implementation,
+    /// e.g. a handle or an index into its streams.
+    pub fn new(raw: usize) -> Self {
+        StreamId(raw)
+    }
+
+    /// Returns the value the `StreamId` was created with.
+    pub fn raw(self) -> usize {
+        self.0
+    }
+}
+
+/// Describes a device as reported by a `Backend`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackendDevice {
+    /// The device name
+    pub name: String,
+    /// The underlying Midi API, e.g. ALSA or CoreMIDI
+    pub interface: String,
+    /// `true` if the device supports input
+    pub input: bool,
+    /// `true` if the device supports output
+    pub output: bool,
+    /// `true` if the device is currently opened
+    pub opened: bool,
+}
+
+/// The Midi system a `PortMidi` context talks to.
+///
+/// `PortMidiBackend` is the default implementation calling the PortMidi C library,
+/// `MockBackend` is an in-memory implementation for tests.
+/// Devices are identified by ids in the range `0..count_devices()`.
+///
+/// # Safety
+///
+/// The methods taking a `StreamId` are `unsafe` to call, since implementations like
+/// `PortMidiBackend` turn the id back into a pointer. Callers must only pass ids returned
+/// by `open_input` or `open_output` of the same backend for streams that are not closed,
+/// must not call the input methods on output streams or vice versa, and must not use a
+/// stream from multiple threads at the same time.
+pub trait Backend: Send + Sync {
+    /// Initializes the Midi system, called once when the `PortMidi` context is created.
+    fn initialize(&self) -> Result<()>;
+
+    /// Terminates the Midi system, called when the `PortMidi` context is dropped.
+    fn terminate(&self) -> Result<()>;
+
+    /// Terminates and initializes the Midi system to re-enumerate the devices,
+    /// called by `PortMidi::rescan`.
+    fn reinitialize(&self) -> Result<()> {
+        self.terminate()?;
+        self.initialize()
+    }
+
+    /// Returns the number of available devices.
+    fn count_devices(&self) -> Result<u32>;
+
+    /// Returns the description of the given device or `None` if the id is invalid.
+    fn device(&self, id: PortMidiDeviceId) -> Option<BackendDevice>;
+
+    /// Returns the id of the default input device, if there is one.
+    fn default_input_device_id(&self) -> Option<PortMidiDeviceId>;
+
+    /// Returns the id of the default output device, if there is one.
+    fn default_output_device_id(&self) -> Option<PortMidiDeviceId>;
+
+    /// Opens an input stream, timestamping events with `time_source` if given.
+    fn open_input(
+        &self,
+        id: PortMidiDeviceId,
+        buffer_size: usize,
+        time_source: Option<Arc<dyn TimeSource>>,
+    ) -> Result<StreamId>;
+
+    /// Opens an output stream with the given latency in milliseconds,
+    /// scheduling events relative to `time_source` if given.
+    fn open_output(
+        &self,
+        id: PortMidiDeviceId,
+        buffer_size: usize,
+        latency: u32,
+        time_source: Option<Arc<dyn TimeSource>>,
+    ) -> Result<StreamId>;
+
+    /// Reads up to `buffer.len()` events into `buffer` and returns the number of events read.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be an open input stream of this backend, see `Backend`.
+    unsafe fn read(&self, stream: StreamId, buffer: &mut [MidiEvent]) -> Result<usize>;
+
+    /// Returns `true` if events are available for reading.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be an open input stream of this backend, see `Backend`.
+    unsafe fn poll(&self, stream: StreamId) -> Result<bool>;
+
+    /// Writes a buffer of events.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be an open output stream of this backend, see `Backend`.
+    unsafe fn write(&self, stream: StreamId, events: &[MidiEvent]) -> Result<()>;
+
+    /// Writes a single message.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be an open output stream of this backend, see `Backend`.
+    unsafe fn write_short(
+        &self,
+        stream: StreamId,
+        timestamp: ffi::PmTimestamp,
+        message: MidiMessage,
+    ) -> Result<()>;
+
+    /// Writes an EOX-terminated SysEx message.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be an open output stream of this backend, see `Backend`.
+    unsafe fn write_sysex(
+        &self,
+        stream: StreamId,
+        timestamp: ffi::PmTimestamp,
+        msg: &[u8],
+    ) -> Result<()>;
+
+    /// Discards the pending output of a stream, it has to be closed afterwards.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be an open output stream of this backend, see `Backend`.
+    unsafe fn abort(&self, stream: StreamId) -> Result<()>;
+
+    /// Returns `true` if the Midi API reported an error for the stream.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be an open stream of this backend, see `Backend`.
+    unsafe fn has_host_error(&self, stream: StreamId) -> bool;
+
+    /// Closes a stream.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be an open stream of this backend, see `Backend`. It must not be used afterwards.
+    unsafe fn close(&self, stream: StreamId) -> Result<()>;
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::os::raw::c_int;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use backend::{Backend, BackendDevice, StreamId};
+use ffi;
+use ffi::MaybeError;
+use time::{TimeProc, TimeSource};
+use types::{Error, MidiEvent, MidiMessage, PortMidiDeviceId, Result};
+
+// PortMidi is global and must only be terminated when the last backend is terminated,
+// and only be re-initialized while no streams are open
+struct Library {
+    initialized: bool,
+    // number of initialized backends
+    users: usize,
+    // number of open streams of all backends
+    streams: usize,
+}
+
+static LIBRARY: Mutex<Library> = Mutex::new(Library {
+    initialized: false,
+    users: 0,
+    streams: 0,
+});
+
+/// The default `Backend`, calling the PortMidi C library.
+///
+/// The library is initialized by the first and terminated by the last backend,
+/// so several `PortMidi` contexts can be used at the same time.
+pub struct PortMidiBackend {
+    // Time procedures of the open streams, they must outlive the stream
+    time_procs: Mutex<HashMap<StreamId, TimeProc>>,
+    // `true` while this backend counts as a user of the library
+    initialized: AtomicBool,
+}
+impl PortMidiBackend {
+    pub fn new() -> Self {
+        PortMidiBackend {
+            time_procs: Mutex::new(HashMap::new()),
+            initialized: AtomicBool::new(false),
+        }
+    }
+
+    fn library() -> MutexGuard<'static, Library> {
+        LIBRARY.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn raw_stream(stream: StreamId) -> *const ffi::PortMidiStream {
+        stream.0 as *const ffi::PortMidiStream
+    }
+
+    // `close` is called when ports are dropped, so a poisoned lock must not panic
+    fn time_procs(&self) -> MutexGuard<'_, HashMap<StreamId, TimeProc>> {
+        self.time_procs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn keep_time_proc(&self, stream: StreamId, time_proc: Option<TimeProc>) {
+        if let Some(time_proc) = time_proc {
+            self.time_procs().insert(stream, time_proc);
+        }
+    }
+}
+impl Default for PortMidiBackend {
+    fn default() -> Self {
+        PortMidiBackend::new()
+    }
+}
+impl Backend for PortMidiBackend {
+    /// Does nothing if this backend is already initialized.
+    fn initialize(&self) -> Result<()> {
+        let mut library = PortMidiBackend::library();
+        if self.initialized.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        if !library.initialized {
+            Result::from(unsafe { ffi::Pm_Initialize() })?;
+            library.initialized = true;
+        }
+        library.users += 1;
+        self.initialized.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Does nothing if this backend is not initialized, so the library is only
+    /// terminated once the last initialized backend is terminated.
+    fn terminate(&self) -> Result<()> {
+        let mut library = PortMidiBackend::library();
+        if !self.initialized.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        library.users -= 1;
+        if library.users == 0 && library.initialized {
+            library.initialized = false;
+            Result::from(unsafe { ffi::Pm_Terminate() })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns an `Error::LibraryInUse` if any backend has open streams,
+    /// re-initializing would close them.
+    ///
+    /// Other backends keep using the library, but the device ids they obtained
+    /// before refer to the old enumeration.
+    fn reinitialize(&self) -> Result<()> {
+        let mut library = PortMidiBackend::library();
+        if library.streams > 0 {
+            return Err(Error::LibraryInUse);
+        }
+        if library.initialized {
+            Result::from(unsafe { ffi::Pm_Terminate() })?;
+            library.initialized = false;
+        }
+        Result::from(unsafe { ffi::Pm_Initialize() })?;
+        library.initialized = true;
+        if !self.initialized.swap(true, Ordering::AcqRel) {
+            library.users += 1;
+        }
+        Ok(())
+    }
+
+    fn count_devices(&self) -> Result<u32> {
+        let device_count = unsafe { ffi::Pm_CountDevices() };
+        if device_count >= 0 {
+            Ok(device_count as u32)
+        } else {
+            Err(Error::InvalidDeviceCount(device_count))
+        }
+    }
+
+    fn device(&self, id: PortMidiDeviceId) -> Option<BackendDevice> {
+        let dev_inf_ptr = unsafe { ffi::Pm_GetDeviceInfo(id) };
+        if dev_inf_ptr.is_null() {
+            None
+        } else {
+            let dev_inf = unsafe { &*dev_inf_ptr };
+            Some(BackendDevice {
+                name: ffi::ptr_to_string(dev_inf.name).unwrap_or_default(),
+                interface: ffi::ptr_to_string(dev_inf.interf).unwrap_or_default(),
+                input: dev_inf.input != 0,
+                output: dev_inf.output != 0,
+                opened: dev_inf.opened != 0,
+            })
+        }
+    }
+
+    fn default_input_device_id(&self) -> Option<PortMidiDeviceId> {
+        match unsafe { ffi::Pm_GetDefaultInputDeviceID() } {
+            ffi::PM_NO_DEVICE => None,
+            id => Some(id),
+        }
+    }
+
+    fn default_output_device_id(&self) -> Option<PortMidiDeviceId> {
+        match unsafe { ffi::Pm_GetDefaultOutputDeviceID() } {
+            ffi::PM_NO_DEVICE => None,
+            id => Some(id),
+        }
+    }
+
+    fn open_input(
+        &self,
+        id: PortMidiDeviceId,
+        buffer_size: usize,
+        time_source: Option<Arc<dyn TimeSource>>,
+    ) -> Result<StreamId> {
+        let time_proc = time_source.map(TimeProc::new);
+        let raw_stream: *const ffi::PortMidiStream = ptr::null();
+        let mut library = PortMidiBackend::library();
+        Result::from(unsafe {
+            ffi::Pm_OpenInput(
+                &raw_stream as *const *const _,
+                id,
+                ptr::null(), // *inputDriverInfo, not needed for normal operation
+                buffer_size as c_int,
+                time_proc.as_ref().and_then(|p| p.time_proc()), // None uses PortTime
+                time_proc.as_ref().map_or(ptr::null(), |p| p.time_info()),
+            ) // time_info, a pointer passed to the time procedure
+        })?;
+        library.streams += 1;
+        let stream = StreamId(raw_stream as usize);
+        self.keep_time_proc(stream, time_proc);
+        Ok(stream)
+    }
+
+    fn open_output(
+        &self,
+        id: PortMidiDeviceId,
+        buffer_size: usize,
+        latency: u32,
+        time_source: Option<Arc<dyn TimeSource>>,
+    ) -> Result<StreamId> {
+        let latency: i32 =
+            std::convert::TryFrom::try_from(latency).map_err(|_| Error::InvalidLatency(latency))?;
+        let time_proc = time_source.map(TimeProc::new);
+        let raw_stream: *const ffi::PortMidiStream = ptr::null();
+        let mut library = PortMidiBackend::library();
+        Result::from(unsafe {
+            ffi::Pm_OpenOutput(
+                &raw_stream as *const *const _,
+                id,
+                ptr::null(), // *inputDriverInfo, not needed for normal operation
+                buffer_size as c_int,
+                time_proc.as_ref().and_then(|p| p.time_proc()), // None uses PortTime
+                time_proc.as_ref().map_or(ptr::null(), |p| p.time_info()),
+                latency,
+            )
+        })?;
+        library.streams += 1;
+        let stream = StreamId(raw_stream as usize);
+        self.keep_time_proc(stream, time_proc);
+        Ok(stream)
+    }
+
+    unsafe fn read(&self, stream: StreamId, buffer: &mut [MidiEvent]) -> Result<usize> {
+        let mut event_buffer = vec![ffi::PmEvent::default(); buffer.len()];
+        let res = unsafe {
+            ffi::Pm_Read(
+                PortMidiBackend::raw_stream(stream),
+                event_buffer.as_mut_ptr(),
+                event_buffer.len() as c_int,
+            )
+        };
+        match ffi::PmError::try_from(res) {
+            Ok(event_cnt) => {
+                let event_cnt = event_cnt as usize;
+                for (event, raw) in buffer.iter_mut().zip(&event_buffer[..event_cnt]) {
+                    *event = MidiEvent::from(*raw);
+                }
+                Ok(event_cnt)
+            }
+            Err(ffi::PmError::PmNoError) => Ok(0),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    unsafe fn poll(&self, stream: StreamId) -> Result<bool> {
+        match unsafe { ffi::Pm_Poll(PortMidiBackend::raw_stream(stream)) } {
+            ffi::PmError::PmNoError => Ok(false),
+            ffi::PmError::PmGotData => Ok(true),
+            err => Err(Error::from(err)),
+        }
+    }
+
+    unsafe fn write(&self, stream: StreamId, events: &[MidiEvent]) -> Result<()> {
+        let events: Vec<ffi::PmEvent> = events.iter().map(|&event| event.into()).collect();
+        Result::from(unsafe {
+            ffi::Pm_Write(
+                PortMidiBackend::raw_stream(stream),
+                events.as_ptr(),
+                events.len() as c_int,
+            )
+        })
+    }
+
+    unsafe fn write_short(
+        &self,
+        stream: StreamId,
+        timestamp: ffi::PmTimestamp,
+        message: MidiMessage,
+    ) -> Result<()> {
+        Result::from(unsafe {
+            ffi::Pm_WriteShort(
+                PortMidiBackend::raw_stream(stream),
+                timestamp,
+                message.into(),
+            )
+        })
+    }
+
+    unsafe fn write_sysex(
+        &self,
+        stream: StreamId,
+        timestamp: ffi::PmTimestamp,
+        msg: &[u8],
+    ) -> Result<()> {
+        Result::from(unsafe {
+            ffi::Pm_WriteSysEx(PortMidiBackend::raw_stream(stream), timestamp, msg.as_ptr())
+        })
+    }
+
+    unsafe fn abort(&self, stream: StreamId) -> Result<()> {
+        Result::from(unsafe { ffi::Pm_Abort(PortMidiBackend::raw_stream(stream)) })
+    }
+
+    unsafe fn has_host_error(&self, stream: StreamId) -> bool {
+        unsafe { ffi::Pm_HasHostError(PortMidiBackend::raw_stream(stream)) != 0 }
+    }
+
+    unsafe fn close(&self, stream: StreamId) -> Result<()> {
+        let res = Result::from(unsafe { ffi::Pm_Close(PortMidiBackend::raw_stream(stream)) });
+        self.time_procs().remove(&stream);
+        PortMidiBackend::library().streams -= 1;
+        res
+    }
+}
@@ -1,33 +1,95 @@
-use device::DeviceInfo;
-use ffi;
-use io::{InputPort, OutputPort};
+use backend::{Backend, PortMidiBackend};
+use device::{DeviceChanges, DeviceInfo};
+use io::{InputPort, OutputPort, OwnedInputPort, OwnedOutputPort};
+use listener::Listener;
+use query::DeviceQuery;
 use std::os::raw::c_int;
-use types::{Error, PortMidiDeviceId, Result};
+use std::sync::{Arc, Mutex, Weak};
+use time::TimeSource;
+use types::{report_drop_error, Error, MidiEvent, PortMidiDeviceId, Result};
+
+// The context handed out by `PortMidi::shared`, while it is in use
+static SHARED: Mutex<Weak<PortMidi>> = Mutex::new(Weak::new());
 
 /// The PortMidi base struct.
 /// Initializes PortMidi on creation and terminates it on drop.
 pub struct PortMidi {
     device_count: u32,
+    // devices of the last enumeration, to report changes on `rescan`
+    known_devices: Vec<DeviceInfo>,
+    backend: Box<dyn Backend>,
+    terminated: bool,
 }
 impl PortMidi {
     /// Initializes the underlying PortMidi C library.
     /// PortMidi does not support *hot plugging*, this means
     /// that devices that are connect after calling `new`
-    /// are not picked up.
+    /// are not picked up until `rescan` is called.
     pub fn new() -> Result<Self> {
-        Result::from(unsafe { ffi::Pm_Initialize() })?;
-        let device_count = unsafe { ffi::Pm_CountDevices() };
-        if device_count >= 0 {
-            Ok(PortMidi {
-                device_count: device_count as u32,
-            })
-        } else {
-            Err(Error::Invalid)
+        PortMidi::with_backend(PortMidiBackend::new())
+    }
+
+    /// Returns the context shared by the whole process, it is created on the first call
+    /// and dropped with its last reference.
+    ///
+    /// Use this to let independent parts of a program open ports without passing a
+    /// context around. Contexts created with `new` share the initialized library as
+    /// well, but enumerate the devices on their own.
+    pub fn shared() -> Result<Arc<PortMidi>> {
+        let mut shared = SHARED.lock().unwrap();
+        if let Some(context) = shared.upgrade() {
+            return Ok(context);
         }
+        let context = Arc::new(PortMidi::new()?);
+        *shared = Arc::downgrade(&context);
+        Ok(context)
     }
 
-    /// Return the number of devices. This number will not change during the lifetime
-    /// of the program.
+    /// Initializes the given `Backend`, e.g. a `MockBackend` for tests.
+    pub fn with_backend<B: Backend + 'static>(backend: B) -> Result<Self> {
+        backend.initialize()?;
+        // the backend is terminated on drop if enumerating the devices fails
+        let mut context = PortMidi {
+            device_count: 0,
+            known_devices: Vec::new(),
+            backend: Box::new(backend),
+            terminated: false,
+        };
+        context.device_count = context.backend.count_devices()?;
+        context.known_devices = context.devices()?;
+        Ok(context)
+    }
+
+    /// Terminates the backend, unlike dropping the context this returns an error
+    /// if terminating fails.
+    pub fn terminate(mut self) -> Result<()> {
+        self.terminated = true;
+        self.backend.terminate()
+    }
+
+    /// Returns the `Backend` of this context.
+    pub(crate) fn backend(&self) -> &dyn Backend {
+        &*self.backend
+    }
+
+    /// Re-initializes the backend to pick up devices that were connected or
+    /// disconnected since the last enumeration and returns the changes.
+    ///
+    /// Device ids are reassigned, so `DeviceInfo`s obtained before are outdated.
+    /// Since ports borrow the context, no ports can be open while rescanning.
+    /// Returns an `Error::LibraryInUse` if another context has open ports, e.g. the one
+    /// returned by `shared`. Other contexts keep the device ids of their last enumeration
+    /// until they are rescanned as well.
+    pub fn rescan(&mut self) -> Result<DeviceChanges> {
+        self.backend.reinitialize()?;
+        self.device_count = self.backend.count_devices()?;
+        let devices = self.devices()?;
+        let changes = DeviceChanges::between(&self.known_devices, &devices);
+        self.known_devices = devices;
+        Ok(changes)
+    }
+
+    /// Return the number of devices. This number will only change when `rescan` is called.
     pub fn device_count(&self) -> PortMidiDeviceId {
         self.device_count as c_int
     }
@@ -35,25 +97,23 @@ impl PortMidi {
     /// Returns the `PortMidiDeviceId` for the default input device, or an `Error::NoDefaultDevice` if
     /// there is no available.
     pub fn default_input_device_id(&self) -> Result<PortMidiDeviceId> {
-        match unsafe { ffi::Pm_GetDefaultInputDeviceID() } {
-            ffi::PM_NO_DEVICE => Err(Error::NoDefaultDevice),
-            id => Ok(id),
-        }
+        self.backend
+            .default_input_device_id()
+            .ok_or(Error::NoDefaultDevice)
     }
 
     /// Returns the `PortMidiDeviceId` for the default output device, or an `Error::NoDefaultDevice` if
     /// there is no available.
     pub fn default_output_device_id(&self) -> Result<PortMidiDeviceId> {
-        match unsafe { ffi::Pm_GetDefaultOutputDeviceID() } {
-            ffi::PM_NO_DEVICE => Err(Error::NoDefaultDevice),
-            id => Ok(id),
-        }
+        self.backend
+            .default_output_device_id()
+            .ok_or(Error::NoDefaultDevice)
     }
 
     /// Returns the `DeviceInfo` for the given device id or an `Error::PortMidi(_)` if
     /// the given id is invalid.
     pub fn device(&self, id: PortMidiDeviceId) -> Result<DeviceInfo> {
-        DeviceInfo::new(id)
+        DeviceInfo::with_backend(&*self.backend, id)
     }
 
     /// Returns a `Vec<DeviceInfo>` containing all known device infos.
@@ -69,6 +129,28 @@ impl PortMidi {
         Ok(devices)
     }
 
+    /// Returns the input device with the given name.
+    ///
+    /// If no device has exactly this name, the devices whose name contains `pattern`
+    /// ignoring case are considered. Returns an `Error::NoMatchingDevice` or an
+    /// `Error::AmbiguousDevice(_)` unless exactly one device is found,
+    /// see `DeviceQuery` for more options.
+    pub fn find_input(&self, pattern: &str) -> Result<DeviceInfo> {
+        self.find_device(DeviceQuery::new().input(), pattern)
+    }
+
+    /// Returns the output device with the given name, see `find_input`.
+    pub fn find_output(&self, pattern: &str) -> Result<DeviceInfo> {
+        self.find_device(DeviceQuery::new().output(), pattern)
+    }
+
+    fn find_device(&self, query: DeviceQuery, pattern: &str) -> Result<DeviceInfo> {
+        match query.clone().name(pattern).find(self) {
+            Err(Error::NoMatchingDevice) => query.name_contains(pattern).ignore_case().find(self),
+            res => res,
+        }
+    }
+
     /// Creates an `InputPort` instance with the given buffer size for the default input device.
     pub fn default_input_port(&self, buffer_size: usize) -> Result<InputPort> {
         let info = self
@@ -87,6 +169,22 @@ impl PortMidi {
         }
     }
 
+    /// Creates an `InputPort` instance for the given device and buffer size that timestamps
+    /// events with the given `TimeSource`.
+    /// If the given device is not an input device an `Error::NotAnInputDevice` is returned.
+    pub fn input_port_with_time_source(
+        &self,
+        device: DeviceInfo,
+        buffer_size: usize,
+        time_source: Arc<dyn TimeSource>,
+    ) -> Result<InputPort<'_>> {
+        if device.is_input() {
+            InputPort::with_time_source(self, device, buffer_size, time_source)
+        } else {
+            Err(Error::NotAnInputDevice)
+        }
+    }
+
     /// Creates an `OutputPort` instance with the given buffer size for the default output device.
     pub fn default_output_port(&self, buffer_size: usize) -> Result<OutputPort> {
         let info = self
@@ -104,11 +202,98 @@ impl PortMidi {
             Err(Error::NotAnOutputDevice)
         }
     }
+
+    /// Creates an `OutputPort` instance for the given device, buffer size and latency
+    /// in milliseconds, see `OutputPort::with_latency`.
+    /// If the given device is not an output device an `Error::NotAnOutputDevice` is returned.
+    pub fn output_port_with_latency(
+        &self,
+        device: DeviceInfo,
+        buffer_size: usize,
+        latency: u32,
+    ) -> Result<OutputPort<'_>> {
+        if device.is_output() {
+            OutputPort::with_latency(self, device, buffer_size, latency)
+        } else {
+            Err(Error::NotAnOutputDevice)
+        }
+    }
+
+    /// Creates an `OutputPort` instance for the given device, buffer size and latency
+    /// that schedules events relative to the given `TimeSource`.
+    /// If the given device is not an output device an `Error::NotAnOutputDevice` is returned.
+    pub fn output_port_with_time_source(
+        &self,
+        device: DeviceInfo,
+        buffer_size: usize,
+        latency: u32,
+        time_source: Arc<dyn TimeSource>,
+    ) -> Result<OutputPort<'_>> {
+        if device.is_output() {
+            OutputPort::with_time_source(self, device, buffer_size, latency, time_source)
+        } else {
+            Err(Error::NotAnOutputDevice)
+        }
+    }
+
+    /// Creates an `InputPort` instance for the given device and buffer size that holds
+    /// a reference to the context instead of borrowing it.
+    /// If the given device is not an input device an `Error::NotAnInputDevice` is returned.
+    pub fn owned_input_port(
+        self: &Arc<Self>,
+        device: DeviceInfo,
+        buffer_size: usize,
+    ) -> Result<OwnedInputPort> {
+        if device.is_input() {
+            InputPort::new(self.clone(), device, buffer_size)
+        } else {
+            Err(Error::NotAnInputDevice)
+        }
+    }
+
+    /// Creates an `OutputPort` instance for the given device and buffer size that holds
+    /// a reference to the context instead of borrowing it.
+    /// If the given device is not an output device an `Error::NotAnOutputDevice` is returned.
+    pub fn owned_output_port(
+        self: &Arc<Self>,
+        device: DeviceInfo,
+        buffer_size: usize,
+    ) -> Result<OwnedOutputPort> {
+        if device.is_output() {
+            OutputPort::new(self.clone(), device, buffer_size)
+        } else {
+            Err(Error::NotAnOutputDevice)
+        }
+    }
+
+    /// Starts a `Listener` thread that reads from an `InputPort` for the given device
+    /// and buffer size and calls `callback` for every received `MidiEvent`.
+    ///
+    /// The listener keeps the context alive, like `Listener::new` and `Scheduler::new` it
+    /// takes an `Arc<PortMidi>`, clone it to keep using the context.
+    /// If the given device is not an input device an `Error::NotAnInputDevice` is returned.
+    pub fn listen<F>(
+        self: Arc<Self>,
+        device: DeviceInfo,
+        buffer_size: usize,
+        callback: F,
+    ) -> Result<Listener>
+    where
+        F: FnMut(MidiEvent) + Send + 'static,
+    {
+        if device.is_input() {
+            Listener::new(self, device, buffer_size, callback)
+        } else {
+            Err(Error::NotAnInputDevice)
+        }
+    }
 }
 impl Drop for PortMidi {
     fn drop(&mut self) {
-        Result::from(unsafe { ffi::Pm_Terminate() })
-            .map_err(|err| println!("Could not terminate: {}", err))
-            .unwrap();
+        if !self.terminated {
+            if let Err(err) = self.backend.terminate() {
+                report_drop_error(&err);
+            }
+        }
     }
 }
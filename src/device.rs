@@ -1,3 +1,4 @@
+use backend::{Backend, PortMidiBackend};
 use ffi;
 use std::fmt;
 use types::*;
@@ -7,51 +8,61 @@ use types::*;
 pub enum Direction {
     Input,
     Output,
+    /// The device supports input as well as output.
+    Duplex,
 }
 
 /// Represents a PortMidi device.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DeviceInfo {
     id: PortMidiDeviceId,
     /// The device name
     name: String,
-    /// Event direction
-    dir: Direction,
+    /// The underlying Midi API
+    interface: String,
+    input: bool,
+    output: bool,
+    /// Opened state at the time the info was queried
+    opened: bool,
 }
 impl DeviceInfo {
-    /// Creates a new `DeviceInfo` instance for the given device id.
+    /// Creates a new `DeviceInfo` instance for the given device id, querying the
+    /// PortMidi C library directly. Prefer `PortMidi::device`, which uses the context's `Backend`.
     /// Returns an `Error::PortMidi(_)` if the given id is invalid.
     pub fn new(id: PortMidiDeviceId) -> Result<Self> {
-        let dev_inf_ptr = unsafe { ffi::Pm_GetDeviceInfo(id) };
-        if dev_inf_ptr.is_null() {
-            Err(Error::PortMidi(ffi::PmError::PmInvalidDeviceId))
-        } else {
-            let name = unsafe { ffi::ptr_to_string((*dev_inf_ptr).name).unwrap() };
-            let direction = if unsafe { (*dev_inf_ptr).input != 0 } {
-                Direction::Input
-            } else {
-                Direction::Output
-            };
+        DeviceInfo::with_backend(&PortMidiBackend::new(), id)
+    }
 
-            Ok(DeviceInfo {
+    /// Creates a new `DeviceInfo` instance for the given device id of the given `Backend`.
+    /// Returns an `Error::PortMidi(_)` if the given id is invalid.
+    pub fn with_backend(backend: &dyn Backend, id: PortMidiDeviceId) -> Result<Self> {
+        match backend.device(id) {
+            None => Err(Error::PortMidi(ffi::PmError::PmInvalidDeviceId)),
+            Some(device) => Ok(DeviceInfo {
                 id,
-                name,
-                dir: direction,
-            })
+                name: device.name,
+                interface: device.interface,
+                input: device.input,
+                output: device.output,
+                opened: device.opened,
+            }),
         }
     }
 
-    /// Returns `true` for an input device.
+    /// Returns `true` if the device supports input.
     pub fn is_input(&self) -> bool {
-        match self.dir {
-            Direction::Input => true,
-            _ => false,
-        }
+        self.input
     }
 
-    /// Returns `true` for an output device.
+    /// Returns `true` if the device supports output.
     pub fn is_output(&self) -> bool {
-        !self.is_input()
+        self.output
+    }
+
+    /// Returns `true` if the device was opened when this info was queried.
+    /// Query the device again, e.g. with `PortMidi::device`, to get the current state.
+    pub fn is_opened(&self) -> bool {
+        self.opened
     }
 
     /// Returns the device name.
@@ -59,18 +70,69 @@ impl DeviceInfo {
         &self.name
     }
 
+    /// Returns the name of the underlying Midi API, e.g. `ALSA`, `CoreMIDI` or `MMSystem`.
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
     /// Returns the device event direction.
     pub fn direction(&self) -> Direction {
-        self.dir
+        match (self.input, self.output) {
+            (true, true) => Direction::Duplex,
+            (true, false) => Direction::Input,
+            _ => Direction::Output,
+        }
     }
 
     /// Returns the device id.
     pub fn id(&self) -> PortMidiDeviceId {
         self.id
     }
+
+    /// Returns `true` if both infos describe the same device, ignoring the id
+    /// and the opened state, which can change when devices are re-enumerated.
+    pub fn is_same_device(&self, other: &DeviceInfo) -> bool {
+        self.name == other.name
+            && self.interface == other.interface
+            && self.input == other.input
+            && self.output == other.output
+    }
 }
 impl fmt::Display for DeviceInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}) {:?}: {}", self.id(), self.direction(), self.name())
     }
 }
+
+/// The devices that were added and removed between two enumerations, see `PortMidi::rescan`.
+///
+/// Devices are compared by name, interface and direction, since ids are reassigned
+/// on every enumeration.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceChanges {
+    /// Devices that were not present before, with their new ids
+    pub added: Vec<DeviceInfo>,
+    /// Devices that are no longer present, with their old ids
+    pub removed: Vec<DeviceInfo>,
+}
+impl DeviceChanges {
+    /// Compares two device lists.
+    pub fn between(old: &[DeviceInfo], new: &[DeviceInfo]) -> Self {
+        let mut removed = old.to_vec();
+        let mut added = Vec::new();
+        for device in new {
+            match removed.iter().position(|d| d.is_same_device(device)) {
+                Some(pos) => {
+                    removed.remove(pos);
+                }
+                None => added.push(device.clone()),
+            }
+        }
+        DeviceChanges { added, removed }
+    }
+
+    /// Returns `true` if no device was added or removed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
@@ -14,7 +14,7 @@ use ffi::types::*;
 extern "C" {
     pub fn Pm_Initialize() -> PmError;
     pub fn Pm_Terminate() -> PmError;
-    fn Pm_HasHostError(stream: *const PortMidiStream) -> c_int;
+    pub fn Pm_HasHostError(stream: *const PortMidiStream) -> c_int;
     pub fn Pm_GetErrorText(errorCode: PmError) -> *const c_char;
     pub fn Pm_GetHostErrorText(msg: *mut c_char, len: c_int);
     pub fn Pm_CountDevices() -> c_int;
@@ -25,19 +25,19 @@ extern "C" {
                         inputDevice: PmDeviceId,
                         inputDriverInfo: *const c_void,
                         bufferSize: i32,
-                        time_proc: *const c_void,
+                        time_proc: PmTimeProcPtr,
                         time_info: *const c_void)
                         -> PmError;
     pub fn Pm_OpenOutput(stream: *const *const PortMidiStream,
                          outputDevice: PmDeviceId,
                          inputDriverInfo: *const c_void,
                          bufferSize: i32,
-                         time_proc: *const c_void,
+                         time_proc: PmTimeProcPtr,
                          time_info: *const c_void,
                          latency: i32)
                          -> PmError;
     pub fn Pm_Read(stream: *const PortMidiStream, buffer: *mut PmEvent, length: c_int) -> c_int;
-    fn Pm_Abort(stream: *const PortMidiStream) -> PmError;
+    pub fn Pm_Abort(stream: *const PortMidiStream) -> PmError;
     pub fn Pm_Close(stream: *const PortMidiStream) -> PmError;
     pub fn Pm_Poll(stream: *const PortMidiStream) -> PmError;
     pub fn Pm_Write(stream: *const PortMidiStream,
@@ -52,4 +52,8 @@ extern "C" {
                          when: PmTimestamp,
                          msg: *const c_uchar)
                          -> PmError;
+    pub fn Pt_Start(resolution: c_int, callback: PtCallback, user_data: *mut c_void) -> PtError;
+    pub fn Pt_Stop() -> PtError;
+    pub fn Pt_Started() -> c_int;
+    pub fn Pt_Time() -> PmTimestamp;
 }
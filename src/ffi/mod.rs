@@ -3,18 +3,23 @@ mod functions;
 pub use self::types::*;
 pub use self::functions::*;
 
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_int};
 use std::ffi::CStr;
 
+/// Converts a C string, replacing invalid UTF-8 sequences, e.g. in device names.
+/// Returns `None` for a null pointer.
 pub fn ptr_to_string(str_ptr: *const c_char) -> Option<String> {
     if !str_ptr.is_null() {
-        match unsafe { CStr::from_ptr(str_ptr) }
-                  .to_str()
-                  .ok() {
-            Some(str_slice) => Some(str_slice.to_owned()),
-            None => None,
-        }
+        Some(unsafe { CStr::from_ptr(str_ptr) }.to_string_lossy().into_owned())
     } else {
         None
     }
 }
+
+/// Returns the text of the last host error, PortMidi keeps it in global state
+/// and clears it when it is read.
+pub fn host_error_text() -> String {
+    let mut text: [c_char; ::PM_HOST_ERROR_MSG_LEN as usize] = [0; ::PM_HOST_ERROR_MSG_LEN as usize];
+    unsafe { Pm_GetHostErrorText(text.as_mut_ptr(), text.len() as c_int) };
+    ptr_to_string(text.as_ptr()).unwrap_or_default()
+}
@@ -9,6 +9,8 @@ pub type PortMidiStream = c_void;
 pub type PmMessage = c_uint;
 
 pub type PmTimestamp = u32;
+pub type PmTimeProcPtr = Option<extern "C" fn(time_info: *mut c_void) -> PmTimestamp>;
+pub type PtCallback = Option<extern "C" fn(timestamp: PmTimestamp, user_data: *mut c_void)>;
 pub const PM_NO_DEVICE: PmDeviceId = -1;
 pub const MIDI_EOX: u8 = 0xf7;
 
@@ -72,18 +74,30 @@ pub enum PmError {
 }
 impl fmt::Display for PmError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut host_error_text: [c_char; 1024] = [0; 1024];
-        let str_ptr = match *self {
-            PmError::PmHostError => unsafe {
-                ffi::Pm_GetHostErrorText(
-                    host_error_text.as_mut_ptr(),
-                    host_error_text.len() as c_int,
-                );
-                host_error_text.as_ptr()
-            },
-            _ => unsafe { ffi::Pm_GetErrorText(*self) },
+        // the host error text is captured by `Error::from`, it is gone by now
+        let str_ptr = unsafe { ffi::Pm_GetErrorText(*self) };
+        write!(f, "{}", ffi::ptr_to_string(str_ptr).unwrap_or_default())
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum PtError {
+    PtNoError = 0,
+    PtHostError = -10000,
+    PtAlreadyStarted = -9999,
+    PtAlreadyStopped = -9998,
+    PtInsufficientMemory = -9997,
+}
+impl fmt::Display for PtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match *self {
+            PtError::PtNoError => "",
+            PtError::PtHostError => "Host error",
+            PtError::PtAlreadyStarted => "Timer already started",
+            PtError::PtAlreadyStopped => "Timer already stopped",
+            PtError::PtInsufficientMemory => "Insufficient memory",
         };
-        write!(f, "{}", ffi::ptr_to_string(str_ptr).unwrap())
+        write!(f, "{}", text)
     }
 }
 pub trait MaybeError<T> {
@@ -1,67 +1,178 @@
+use backend::StreamId;
 use context::PortMidi;
 use device::DeviceInfo;
 use ffi;
-use ffi::MaybeError;
-use std::marker::Send;
-use std::os::raw::c_int;
-use std::ptr;
+use std::cmp;
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use time::TimeSource;
 use types::*;
 
+const MIN_BACKOFF_US: u64 = 50;
+pub(crate) const MAX_BACKOFF_US: u64 = 1000;
+
+/// Sleep interval that doubles on every wait, used to wait for input without busy-polling.
+///
+/// The interval is capped at 1 ms, so the added latency stays below that
+/// even when the port was idle for a long time.
+pub(crate) struct Backoff {
+    delay: Duration,
+    max: Duration,
+}
+impl Backoff {
+    pub(crate) fn new() -> Self {
+        Backoff::with_max(Duration::from_micros(MAX_BACKOFF_US))
+    }
+
+    /// Creates a backoff whose interval is capped at `max` instead of 1 ms.
+    pub(crate) fn with_max(max: Duration) -> Self {
+        Backoff {
+            delay: cmp::min(Duration::from_micros(MIN_BACKOFF_US), max),
+            max,
+        }
+    }
+
+    /// Returns the current interval and doubles it for the next call.
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = cmp::min(delay * 2, self.max);
+        delay
+    }
+
+    /// Sleeps for the current interval.
+    pub(crate) fn wait(&mut self) {
+        thread::sleep(self.next_delay());
+    }
+}
+
+/// The context a port belongs to, either borrowed or shared.
+///
+/// Ports opened with a shared context are `InputPort<'static>` and `OutputPort<'static>`,
+/// which can be stored in structs and moved to other threads freely.
+#[derive(Clone)]
+pub enum PortContext<'a> {
+    /// A borrowed context, the port cannot outlive it
+    Borrowed(&'a PortMidi),
+    /// A shared context, kept alive by the port
+    Shared(Arc<PortMidi>),
+}
+impl<'a> Deref for PortContext<'a> {
+    type Target = PortMidi;
+
+    fn deref(&self) -> &PortMidi {
+        match *self {
+            PortContext::Borrowed(context) => context,
+            PortContext::Shared(ref context) => context,
+        }
+    }
+}
+impl<'a> From<&'a PortMidi> for PortContext<'a> {
+    fn from(context: &'a PortMidi) -> Self {
+        PortContext::Borrowed(context)
+    }
+}
+impl<'a> From<&'a Arc<PortMidi>> for PortContext<'a> {
+    fn from(context: &'a Arc<PortMidi>) -> Self {
+        PortContext::Borrowed(context)
+    }
+}
+impl<'a> From<Arc<PortMidi>> for PortContext<'a> {
+    fn from(context: Arc<PortMidi>) -> Self {
+        PortContext::Shared(context)
+    }
+}
+
+/// An `InputPort` that owns a reference to its context.
+pub type OwnedInputPort = InputPort<'static>;
+
+/// An `OutputPort` that owns a reference to its context.
+pub type OwnedOutputPort = OutputPort<'static>;
+
 /// Represents the input port of a PortMidi device.
+///
+/// The `context` is a `&PortMidi` or an `Arc<PortMidi>`, see `PortContext`.
 pub struct InputPort<'a> {
-    stream: *const ffi::PortMidiStream,
+    stream: StreamId,
     buffer_size: usize,
-    _context: &'a PortMidi, // Used for lifetime pinning
+    context: PortContext<'a>,
     device: DeviceInfo,
+    closed: bool,
+    // methods like `read_n` use the stream through `&self`, so the port must not be `Sync`
+    not_sync: PhantomData<*const ()>,
 }
+// the stream may be used from any thread, as long as it is one at a time
+unsafe impl<'a> Send for InputPort<'a> {}
 impl<'a> InputPort<'a> {
     /// Construct a new `InputPort` for the given device and buffer size.
     ///
+    /// If the `device` is not an input device an `Error::NotAnInputDevice` is returned,
+    /// if it is already opened an `Error::DeviceInUse`.
+    pub fn new<C: Into<PortContext<'a>>>(
+        context: C,
+        device: DeviceInfo,
+        buffer_size: usize,
+    ) -> Result<InputPort<'a>> {
+        InputPort::open(context.into(), device, buffer_size, None)
+    }
+
+    /// Construct a new `InputPort` for the given device and buffer size that
+    /// timestamps the received events with the given `TimeSource`.
+    ///
     /// If the `device` is not an input device an `Error::NotAnInputDevice` is returned.
-    pub fn new(context: &'a PortMidi, device: DeviceInfo, buffer_size: usize) -> Result<InputPort> {
-        if device.is_output() {
+    pub fn with_time_source<C: Into<PortContext<'a>>>(
+        context: C,
+        device: DeviceInfo,
+        buffer_size: usize,
+        time_source: Arc<dyn TimeSource>,
+    ) -> Result<InputPort<'a>> {
+        InputPort::open(context.into(), device, buffer_size, Some(time_source))
+    }
+
+    fn open(
+        context: PortContext<'a>,
+        device: DeviceInfo,
+        buffer_size: usize,
+        time_source: Option<Arc<dyn TimeSource>>,
+    ) -> Result<InputPort<'a>> {
+        // validate against the current state, `device` may be outdated
+        let current = context.device(device.id())?;
+        if !current.is_input() {
             return Err(Error::NotAnInputDevice);
         }
-        let raw_stream: *const ffi::PortMidiStream = ptr::null();
-        Result::from(unsafe {
-            ffi::Pm_OpenInput(
-                &raw_stream as *const *const _,
-                device.id(),
-                ptr::null(), // *inputDriverInfo, not needed for normal operation
-                buffer_size as c_int,
-                ptr::null(), // PmTimeProcPtr, a procedure that returns time in ms
-                ptr::null(),
-            ) // time_info, a pointer passed to the time procedure
-        })?;
+        if current.is_opened() {
+            return Err(Error::DeviceInUse);
+        }
+        let stream = context
+            .backend()
+            .open_input(device.id(), buffer_size, time_source)
+            .map_err(|err| err.on_device(Operation::Open, &device))?;
 
         Ok(InputPort {
-            stream: raw_stream,
+            stream,
             buffer_size,
-            _context: context,
+            context,
             device,
+            closed: false,
+            not_sync: PhantomData,
         })
     }
 
     /// Returns a `Vec<MidiEvent>` with at most `cnt` elements.
     /// If there was no Midi event available, `None` is returned.
-    /// If PortMidi fails to read from the device an `Error::PortMidi(_)` is returned.
+    /// If PortMidi fails to read from the device an `Error::Device { .. }` is returned.
     pub fn read_n(&self, cnt: usize) -> Result<Option<Vec<MidiEvent>>> {
-        let read_cnt = if cnt > self.buffer_size {
-            self.buffer_size as c_int
-        } else {
-            cnt as c_int
-        };
-        let mut event_buffer = vec![ffi::PmEvent::default(); self.buffer_size];
-        let res = unsafe { ffi::Pm_Read(self.stream, event_buffer.as_mut_ptr(), read_cnt) };
-        match ffi::PmError::try_from(res) {
-            Ok(event_cnt) => {
-                let events = (0..event_cnt as usize)
-                    .map(|i| MidiEvent::from(event_buffer[i]))
-                    .collect::<Vec<MidiEvent>>();
+        let read_cnt = cmp::min(cnt, self.buffer_size);
+        let mut events = vec![MidiEvent::from(MidiMessage::from(0)); read_cnt];
+        match self.backend_read(&mut events)? {
+            0 => Ok(None),
+            event_cnt => {
+                events.truncate(event_cnt);
                 Ok(Some(events))
             }
-            Err(ffi::PmError::PmNoError) => Ok(None),
-            Err(err) => Err(Error::PortMidi(err)),
         }
     }
 
@@ -69,101 +180,281 @@ impl<'a> InputPort<'a> {
     ///
     /// A `Result` of `None` means no event was available.
     pub fn read(&mut self) -> Result<Option<MidiEvent>> {
-        let mut event = ffi::PmEvent::default();
-        let read_cnt = 1;
-        let res = unsafe { ffi::Pm_Read(self.stream, &mut event, read_cnt) };
-        match ffi::PmError::try_from(res) {
-            Ok(0) => Ok(None),
-            Ok(_) => Ok(Some(MidiEvent::from(event))),
-            Err(ffi::PmError::PmNoError) => Ok(None),
-            Err(err) => Err(Error::PortMidi(err)),
+        let mut event = [MidiEvent::from(MidiMessage::from(0))];
+        match self.backend_read(&mut event)? {
+            0 => Ok(None),
+            _ => Ok(Some(event[0])),
+        }
+    }
+
+    /// Blocks until a `MidiEvent` is available and returns it.
+    ///
+    /// PortMidi has no blocking read, the port is polled with an increasing
+    /// interval of at most 1 ms while no data is available.
+    pub fn read_blocking(&mut self) -> Result<MidiEvent> {
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(event) = self.read()? {
+                return Ok(event);
+            }
+            backoff.wait();
+        }
+    }
+
+    /// Like `read_blocking`, but waits at most `timeout` for a `MidiEvent`.
+    ///
+    /// A `Result` of `None` means no event arrived in time.
+    pub fn read_timeout(&mut self, timeout: Duration) -> Result<Option<MidiEvent>> {
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(event) = self.read()? {
+                return Ok(Some(event));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(cmp::min(backoff.next_delay(), deadline - now));
+        }
+    }
+
+    /// Returns an iterator over the currently buffered `MidiEvent`s.
+    /// The iterator ends as soon as no more events are available.
+    pub fn iter<'p>(&'p mut self) -> Iter<'p, 'a> {
+        Iter {
+            port: self,
+            done: false,
+        }
+    }
+
+    /// Returns an iterator that blocks until the next `MidiEvent` is available,
+    /// see `read_blocking`.
+    /// The iterator only ends after it returned an error.
+    pub fn events<'p>(&'p mut self) -> Events<'p, 'a> {
+        Events {
+            port: self,
+            done: false,
         }
     }
 
     /// Polls for available Midi events.
     /// Returns `true` if there are events available, otherwise `false` is returned.
-    /// If the polling fails an `Error::PortMidi(_)` is returned.
+    /// If the polling fails an `Error::Device { .. }` is returned.
     pub fn poll(&self) -> Result<bool> {
-        let pm_error = unsafe { ffi::Pm_Poll(self.stream) };
-        match pm_error {
-            ffi::PmError::PmNoError => Ok(false),
-            ffi::PmError::PmGotData => Ok(true),
-            err => Err(Error::PortMidi(err)),
-        }
+        unsafe { self.context.backend().poll(self.stream) }
+            .map_err(|err| err.on_device(Operation::Poll, &self.device))
+    }
+
+    fn backend_read(&self, buffer: &mut [MidiEvent]) -> Result<usize> {
+        unsafe { self.context.backend().read(self.stream, buffer) }
+            .map_err(|err| err.on_device(Operation::Read, &self.device))
     }
 
     /// Returns the `DeviceInfo` of the Midi device that owns this port.
     pub fn device(&self) -> DeviceInfo {
         self.device.clone()
     }
+
+    /// Returns `true` if the Midi API reported an error for this port, e.g. because
+    /// the driver failed.
+    pub fn has_host_error(&self) -> bool {
+        unsafe { self.context.backend().has_host_error(self.stream) }
+    }
+
+    /// Closes the port, unlike dropping it this returns an error if closing fails.
+    pub fn close(mut self) -> Result<()> {
+        self.close_stream()
+    }
+
+    fn close_stream(&mut self) -> Result<()> {
+        self.closed = true;
+        unsafe { self.context.backend().close(self.stream) }
+            .map_err(|err| err.on_device(Operation::Close, &self.device))
+    }
 }
 impl<'a> Drop for InputPort<'a> {
     fn drop(&mut self) {
-        if let Err(err) = Result::from(unsafe { ffi::Pm_Close(self.stream) }) {
-            println!("{}", err);
+        if !self.closed {
+            if let Err(err) = self.close_stream() {
+                report_drop_error(&err);
+            }
         }
     }
 }
-unsafe impl<'a> Send for InputPort<'a> {}
+
+/// Iterator over the buffered events of an `InputPort`, created by `InputPort::iter`.
+pub struct Iter<'p, 'a: 'p> {
+    port: &'p mut InputPort<'a>,
+    done: bool,
+}
+impl<'p, 'a> Iterator for Iter<'p, 'a> {
+    type Item = Result<MidiEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.port.read() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Blocking iterator over the events of an `InputPort`, created by `InputPort::events`.
+pub struct Events<'p, 'a: 'p> {
+    port: &'p mut InputPort<'a>,
+    done: bool,
+}
+impl<'p, 'a> Iterator for Events<'p, 'a> {
+    type Item = Result<MidiEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.port.read_blocking();
+        self.done = res.is_err();
+        Some(res)
+    }
+}
 
 /// Represents the output port of a PortMidi device.
+///
+/// The `context` is a `&PortMidi` or an `Arc<PortMidi>`, see `PortContext`.
 pub struct OutputPort<'a> {
-    stream: *const ffi::PortMidiStream,
-    _context: &'a PortMidi, // Used for lifetime pinning
+    stream: StreamId,
+    context: PortContext<'a>,
     device: DeviceInfo,
+    latency: u32,
+    closed: bool,
+    // `write_sysex` uses the stream through `&self`, so the port must not be `Sync`
+    not_sync: PhantomData<*const ()>,
 }
+// the stream may be used from any thread, as long as it is one at a time
+unsafe impl<'a> Send for OutputPort<'a> {}
 impl<'a> OutputPort<'a> {
     /// Construct a new `OutputPort` for the given device and buffer size.
     ///
+    /// The port is opened with a latency of `0`, this means that the timestamps of
+    /// written events are ignored and every event is sent immediately.
+    ///
+    /// If the `device` is not an output device an `Error::NotAnOutputDevice` is returned,
+    /// if it is already opened an `Error::DeviceInUse`.
+    pub fn new<C: Into<PortContext<'a>>>(
+        context: C,
+        device: DeviceInfo,
+        buffer_size: usize,
+    ) -> Result<OutputPort<'a>> {
+        OutputPort::with_latency(context, device, buffer_size, 0)
+    }
+
+    /// Construct a new `OutputPort` for the given device, buffer size and latency in milliseconds.
+    ///
+    /// If `latency` is greater than `0`, events are sent at their timestamp plus the latency,
+    /// where timestamps are in milliseconds of the PortTime clock. This allows scheduling
+    /// events ahead of time, events with a timestamp of `0` are sent immediately.
+    ///
+    /// If the `device` is not an output device an `Error::NotAnOutputDevice` is returned,
+    /// if `latency` exceeds `i32::MAX` an `Error::InvalidLatency(_)`.
+    pub fn with_latency<C: Into<PortContext<'a>>>(
+        context: C,
+        device: DeviceInfo,
+        buffer_size: usize,
+        latency: u32,
+    ) -> Result<OutputPort<'a>> {
+        OutputPort::open(context.into(), device, buffer_size, latency, None)
+    }
+
+    /// Construct a new `OutputPort` for the given device, buffer size and latency that
+    /// schedules events relative to the given `TimeSource`, see `OutputPort::with_latency`.
+    ///
     /// If the `device` is not an output device an `Error::NotAnOutputDevice` is returned.
-    pub fn new(
-        context: &'a PortMidi,
+    pub fn with_time_source<C: Into<PortContext<'a>>>(
+        context: C,
         device: DeviceInfo,
         buffer_size: usize,
-    ) -> Result<OutputPort> {
-        if device.is_input() {
+        latency: u32,
+        time_source: Arc<dyn TimeSource>,
+    ) -> Result<OutputPort<'a>> {
+        OutputPort::open(
+            context.into(),
+            device,
+            buffer_size,
+            latency,
+            Some(time_source),
+        )
+    }
+
+    fn open(
+        context: PortContext<'a>,
+        device: DeviceInfo,
+        buffer_size: usize,
+        latency: u32,
+        time_source: Option<Arc<dyn TimeSource>>,
+    ) -> Result<OutputPort<'a>> {
+        if i32::try_from(latency).is_err() {
+            return Err(Error::InvalidLatency(latency));
+        }
+        // validate against the current state, `device` may be outdated
+        let current = context.device(device.id())?;
+        if !current.is_output() {
             return Err(Error::NotAnOutputDevice);
         }
-        let raw_stream: *const ffi::PortMidiStream = ptr::null();
-        Result::from(unsafe {
-            ffi::Pm_OpenOutput(
-                &raw_stream as *const *const _,
-                device.id(),
-                ptr::null(), // *inputDriverInfo, not needed for normal operation
-                buffer_size as c_int,
-                ptr::null(), // PmTimeProcPtr, a procedure that returns time in ms,
-                ptr::null(), // time_info, a pointer passed to the time procedure
-                0,
-            ) //latency
-        })?;
+        if current.is_opened() {
+            return Err(Error::DeviceInUse);
+        }
+        let stream = context
+            .backend()
+            .open_output(device.id(), buffer_size, latency, time_source)
+            .map_err(|err| err.on_device(Operation::Open, &device))?;
 
         Ok(OutputPort {
-            stream: raw_stream,
-            _context: context,
+            stream,
+            context,
             device,
+            latency,
+            closed: false,
+            not_sync: PhantomData,
         })
     }
 
+    /// Returns the latency in milliseconds the port was opened with.
+    pub fn latency(&self) -> u32 {
+        self.latency
+    }
+
     /// Write a single `MidiEvent`.
-    /// Returns an `Error::PortMidi(_)` if something went wrong.
+    /// Returns an `Error::Device { .. }` if something went wrong.
     pub fn write_event<T: Into<MidiEvent>>(&mut self, midi_event: T) -> Result<()> {
         self.write_events(vec![midi_event])
     }
 
     /// Write a buffer of midi events to the output port.
-    /// Returns an `Error::PortMidi(_)` if something went wrong.
+    /// Returns an `Error::Device { .. }` if something went wrong.
     pub fn write_events<T: Into<MidiEvent>>(&mut self, midi_events: Vec<T>) -> Result<()> {
-        let events: Vec<ffi::PmEvent> = midi_events
-            .into_iter()
-            .map(|event| event.into().into())
-            .collect();
-        Result::from(unsafe { ffi::Pm_Write(self.stream, events.as_ptr(), events.len() as c_int) })
+        let events: Vec<MidiEvent> = midi_events.into_iter().map(|event| event.into()).collect();
+        unsafe { self.context.backend().write(self.stream, &events) }
+            .map_err(|err| err.on_device(Operation::Write, &self.device))
     }
 
-    /// Write a single `MidiMessage`.
-    /// Returns an `Error::PortMidi(_)` if something went wrong.
+    /// Write a single `MidiMessage` with a timestamp of `0`, so it is sent immediately.
+    /// Returns an `Error::Device { .. }` if something went wrong.
     pub fn write_message<T: Into<MidiMessage>>(&mut self, midi_message: T) -> Result<()> {
-        Result::from(unsafe { ffi::Pm_WriteShort(self.stream, 0, midi_message.into().into()) })
+        unsafe {
+            self.context
+                .backend()
+                .write_short(self.stream, 0, midi_message.into())
+        }
+        .map_err(|err| err.on_device(Operation::Write, &self.device))
     }
 
     /// Returns the `DeviceInfo` of the Midi device that owns this port.
@@ -171,21 +462,58 @@ impl<'a> OutputPort<'a> {
         self.device.clone()
     }
 
-    // Write arbitrarily long EOX-terminated data
+    /// Write arbitrarily long EOX-terminated data.
+    /// The `timestamp` is only honored if the port was opened with a latency.
+    /// Returns an `Error::MissingEox` if `msg` does not end with EOX.
     pub fn write_sysex(&self, timestamp: ffi::PmTimestamp, msg: &[u8]) -> Result<()> {
         // Sysex writes MUST be EOX-terminated
         if Some(&ffi::MIDI_EOX) != msg.last() {
-            Err(Error::Invalid)
+            Err(Error::MissingEox)
         } else {
-            Result::from(unsafe { ffi::Pm_WriteSysEx(self.stream, timestamp, msg.as_ptr()) })
+            unsafe {
+                self.context
+                    .backend()
+                    .write_sysex(self.stream, timestamp, msg)
+            }
+            .map_err(|err| err.on_device(Operation::Write, &self.device))
         }
     }
+
+    /// Returns `true` if the Midi API reported an error for this port, e.g. because
+    /// the driver failed.
+    pub fn has_host_error(&self) -> bool {
+        unsafe { self.context.backend().has_host_error(self.stream) }
+    }
+
+    /// Discards all events that are waiting for their timestamp and closes the port,
+    /// e.g. to silence scheduled output when the transport stops.
+    ///
+    /// PortMidi requires closing the port after aborting, so the port is consumed.
+    /// Messages may be cut off, so a device can miss a note off or the end of a SysEx message.
+    pub fn abort(mut self) -> Result<()> {
+        let aborted = unsafe { self.context.backend().abort(self.stream) }
+            .map_err(|err| err.on_device(Operation::Abort, &self.device));
+        let closed = self.close_stream();
+        aborted.and(closed)
+    }
+
+    /// Closes the port, unlike dropping it this returns an error if closing fails.
+    pub fn close(mut self) -> Result<()> {
+        self.close_stream()
+    }
+
+    fn close_stream(&mut self) -> Result<()> {
+        self.closed = true;
+        unsafe { self.context.backend().close(self.stream) }
+            .map_err(|err| err.on_device(Operation::Close, &self.device))
+    }
 }
 impl<'a> Drop for OutputPort<'a> {
     fn drop(&mut self) {
-        if let Err(err) = Result::from(unsafe { ffi::Pm_Close(self.stream) }) {
-            println!("{}", err);
+        if !self.closed {
+            if let Err(err) = self.close_stream() {
+                report_drop_error(&err);
+            }
         }
     }
 }
-unsafe impl<'a> Send for OutputPort<'a> {}
@@ -5,17 +5,47 @@
 //          Apache License, Version 2.0, (LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0)
 //          MIT license (LICENSE-MIT or http://opensource.org/licenses/MIT).
 // This file may not be copied, modified, or distributed except according to those terms.
+#[cfg(feature = "async")]
+extern crate futures_core;
+#[cfg(feature = "async")]
+extern crate futures_sink;
+
 mod ffi;
+pub mod backend;
+pub use backend::*;
 mod device;
 pub use device::*;
 mod io;
 pub use io::*;
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(feature = "async")]
+pub use stream::*;
 
-pub use ffi::PmError;
+pub use ffi::{PmError, PtError};
 pub mod types;
 pub use types::*;
+mod message;
+pub use message::*;
+mod sysex;
+pub use sysex::*;
 mod context;
 pub use context::*;
+mod listener;
+pub use listener::*;
+mod query;
+pub use query::*;
+mod time;
+pub use time::*;
+mod watcher;
+pub use watcher::*;
+pub mod smf;
+mod recorder;
+pub use recorder::*;
+mod player;
+pub use player::*;
+mod scheduler;
+pub use scheduler::*;
 
 pub const HDRLENGTH: i32 = 50;
 pub const PM_HOST_ERROR_MSG_LEN: i32 = 256;
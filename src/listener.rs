@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use context::PortMidi;
+use device::DeviceInfo;
+use types::{Error, MidiEvent, Result};
+
+const STOP_CHECK_INTERVAL_MS: u64 = 10;
+
+/// Handle of a background thread that reads from an `InputPort` and passes
+/// every `MidiEvent` to a callback, created by `PortMidi::listen`.
+///
+/// The thread is stopped and joined when the handle is dropped.
+pub struct Listener {
+    device: DeviceInfo,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+impl Listener {
+    /// Opens an input port for the given device and buffer size and starts a thread
+    /// that calls `callback` for every event that is read from it.
+    ///
+    /// Returns an error if the port could not be opened.
+    pub fn new<F>(
+        context: Arc<PortMidi>,
+        device: DeviceInfo,
+        buffer_size: usize,
+        mut callback: F,
+    ) -> Result<Listener>
+    where
+        F: FnMut(MidiEvent) + Send + 'static,
+    {
+        let mut port = context.owned_input_port(device.clone(), buffer_size)?;
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = thread::spawn({
+            let running = running.clone();
+            move || {
+                let timeout = Duration::from_millis(STOP_CHECK_INTERVAL_MS);
+                while running.load(Ordering::Acquire) {
+                    if let Some(event) = port.read_timeout(timeout)? {
+                        callback(event);
+                    }
+                }
+                Ok(())
+            }
+        });
+        Ok(Listener {
+            device,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the `DeviceInfo` of the Midi device the listener reads from.
+    pub fn device(&self) -> DeviceInfo {
+        self.device.clone()
+    }
+
+    /// Returns `false` if the listener thread has stopped because of a read error.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+            && self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    /// Stops the listener thread, waits for it to finish and returns the
+    /// read error that stopped it, if any.
+    pub fn stop(mut self) -> Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.running.store(false, Ordering::Release);
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or(Err(Error::Unknown)),
+            None => Ok(()),
+        }
+    }
+}
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
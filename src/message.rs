@@ -0,0 +1,460 @@
+use std::convert::TryFrom;
+
+use types::{Error, MidiEvent, MidiMessage, Result};
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const POLY_AFTERTOUCH: u8 = 0xA0;
+const CONTROL_CHANGE: u8 = 0xB0;
+const PROGRAM_CHANGE: u8 = 0xC0;
+const CHANNEL_PRESSURE: u8 = 0xD0;
+const PITCH_BEND: u8 = 0xE0;
+const MTC_QUARTER_FRAME: u8 = 0xF1;
+const SONG_POSITION: u8 = 0xF2;
+const SONG_SELECT: u8 = 0xF3;
+const TUNE_REQUEST: u8 = 0xF6;
+const TIMING_CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const CONTINUE: u8 = 0xFB;
+const STOP: u8 = 0xFC;
+const ACTIVE_SENSING: u8 = 0xFE;
+const SYSTEM_RESET: u8 = 0xFF;
+
+/// A Midi channel in the range `0..=15`.
+///
+/// Note that the channel is zero based, the channel usually displayed as *1*
+/// is `Channel::new(0)`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Channel(u8);
+impl Channel {
+    /// Returns the channel for the given zero based index or `None` if `index > 15`.
+    pub fn new(index: u8) -> Option<Self> {
+        if index < 16 {
+            Some(Channel(index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the zero based channel index.
+    pub fn index(self) -> u8 {
+        self.0
+    }
+}
+impl From<Channel> for u8 {
+    fn from(channel: Channel) -> Self {
+        channel.0
+    }
+}
+
+/// A 7-bit Midi data value in the range `0..=127`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct U7(u8);
+impl U7 {
+    pub const MIN: U7 = U7(0);
+    pub const MAX: U7 = U7(0x7F);
+
+    /// Returns the value or `None` if `value > 127`.
+    pub fn new(value: u8) -> Option<Self> {
+        if value <= 0x7F {
+            Some(U7(value))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value with the most significant bit cleared.
+    pub fn from_masked(value: u8) -> Self {
+        U7(value & 0x7F)
+    }
+
+    /// Returns the raw value.
+    pub fn value(self) -> u8 {
+        self.0
+    }
+}
+impl From<U7> for u8 {
+    fn from(value: U7) -> Self {
+        value.0
+    }
+}
+
+/// A 14-bit Midi data value in the range `0..=16383`, as used by pitch bend messages.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct U14(u16);
+impl U14 {
+    pub const MIN: U14 = U14(0);
+    pub const MAX: U14 = U14(0x3FFF);
+    /// The pitch bend value meaning *no bend*.
+    pub const CENTER: U14 = U14(0x2000);
+
+    /// Returns the value or `None` if `value > 16383`.
+    pub fn new(value: u16) -> Option<Self> {
+        if value <= 0x3FFF {
+            Some(U14(value))
+        } else {
+            None
+        }
+    }
+
+    /// Combines the least and most significant 7-bit halves into a single value.
+    pub fn from_halves(lsb: U7, msb: U7) -> Self {
+        U14(u16::from(msb.0) << 7 | u16::from(lsb.0))
+    }
+
+    /// Returns the least significant 7 bits.
+    pub fn lsb(self) -> U7 {
+        U7((self.0 & 0x7F) as u8)
+    }
+
+    /// Returns the most significant 7 bits.
+    pub fn msb(self) -> U7 {
+        U7((self.0 >> 7) as u8)
+    }
+
+    /// Returns the raw value.
+    pub fn value(self) -> u16 {
+        self.0
+    }
+}
+impl From<U14> for u16 {
+    fn from(value: U14) -> Self {
+        value.0
+    }
+}
+
+/// A decoded Midi channel voice message.
+///
+/// Use `ChannelMessage::try_from` to decode a `MidiMessage` and `MidiMessage::from` to
+/// encode it again, e.g. to pass it to `OutputPort::write_message`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChannelMessage {
+    NoteOff {
+        channel: Channel,
+        note: U7,
+        velocity: U7,
+    },
+    /// Note that many devices send a `NoteOn` with a velocity of `0` instead of a `NoteOff`.
+    NoteOn {
+        channel: Channel,
+        note: U7,
+        velocity: U7,
+    },
+    PolyAftertouch {
+        channel: Channel,
+        note: U7,
+        pressure: U7,
+    },
+    ControlChange {
+        channel: Channel,
+        controller: U7,
+        value: U7,
+    },
+    ProgramChange {
+        channel: Channel,
+        program: U7,
+    },
+    ChannelPressure {
+        channel: Channel,
+        pressure: U7,
+    },
+    PitchBend {
+        channel: Channel,
+        value: U14,
+    },
+}
+impl ChannelMessage {
+    /// Returns the channel the message is addressed to.
+    pub fn channel(&self) -> Channel {
+        match *self {
+            ChannelMessage::NoteOff { channel, .. }
+            | ChannelMessage::NoteOn { channel, .. }
+            | ChannelMessage::PolyAftertouch { channel, .. }
+            | ChannelMessage::ControlChange { channel, .. }
+            | ChannelMessage::ProgramChange { channel, .. }
+            | ChannelMessage::ChannelPressure { channel, .. }
+            | ChannelMessage::PitchBend { channel, .. } => channel,
+        }
+    }
+}
+/// Decodes a channel voice message.
+///
+/// Returns an `Error::InvalidMessage` if the status byte does not denote a channel voice message
+/// or one of the used data bytes has its most significant bit set.
+/// Unused data bytes are ignored.
+impl TryFrom<MidiMessage> for ChannelMessage {
+    type Error = Error;
+
+    fn try_from(msg: MidiMessage) -> Result<Self> {
+        let channel = Channel(msg.status & 0x0F);
+        let data1 = U7::new(msg.data1).ok_or(Error::InvalidMessage);
+        let data2 = U7::new(msg.data2).ok_or(Error::InvalidMessage);
+        match msg.status & 0xF0 {
+            NOTE_OFF => Ok(ChannelMessage::NoteOff {
+                channel,
+                note: data1?,
+                velocity: data2?,
+            }),
+            NOTE_ON => Ok(ChannelMessage::NoteOn {
+                channel,
+                note: data1?,
+                velocity: data2?,
+            }),
+            POLY_AFTERTOUCH => Ok(ChannelMessage::PolyAftertouch {
+                channel,
+                note: data1?,
+                pressure: data2?,
+            }),
+            CONTROL_CHANGE => Ok(ChannelMessage::ControlChange {
+                channel,
+                controller: data1?,
+                value: data2?,
+            }),
+            PROGRAM_CHANGE => Ok(ChannelMessage::ProgramChange {
+                channel,
+                program: data1?,
+            }),
+            CHANNEL_PRESSURE => Ok(ChannelMessage::ChannelPressure {
+                channel,
+                pressure: data1?,
+            }),
+            PITCH_BEND => Ok(ChannelMessage::PitchBend {
+                channel,
+                value: U14::from_halves(data1?, data2?),
+            }),
+            _ => Err(Error::InvalidMessage),
+        }
+    }
+}
+impl From<ChannelMessage> for MidiMessage {
+    fn from(msg: ChannelMessage) -> Self {
+        let (status, data1, data2) = match msg {
+            ChannelMessage::NoteOff { note, velocity, .. } => (NOTE_OFF, note.0, velocity.0),
+            ChannelMessage::NoteOn { note, velocity, .. } => (NOTE_ON, note.0, velocity.0),
+            ChannelMessage::PolyAftertouch { note, pressure, .. } => {
+                (POLY_AFTERTOUCH, note.0, pressure.0)
+            }
+            ChannelMessage::ControlChange {
+                controller, value, ..
+            } => (CONTROL_CHANGE, controller.0, value.0),
+            ChannelMessage::ProgramChange { program, .. } => (PROGRAM_CHANGE, program.0, 0),
+            ChannelMessage::ChannelPressure { pressure, .. } => (CHANNEL_PRESSURE, pressure.0, 0),
+            ChannelMessage::PitchBend { value, .. } => (PITCH_BEND, value.lsb().0, value.msb().0),
+        };
+        MidiMessage {
+            status: status | msg.channel().0,
+            data1,
+            data2,
+            data3: 0,
+        }
+    }
+}
+impl From<ChannelMessage> for MidiEvent {
+    fn from(msg: ChannelMessage) -> Self {
+        MidiEvent::from(MidiMessage::from(msg))
+    }
+}
+
+/// A decoded Midi system common message.
+///
+/// SysEx messages are not covered since they do not fit into a single `MidiMessage`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SystemCommon {
+    /// A Midi time code quarter frame. `message_type` is in the range `0..=7`
+    /// and `value` in the range `0..=15`, out of range bits are masked off when encoding.
+    MtcQuarterFrame {
+        message_type: u8,
+        value: u8,
+    },
+    /// The song position in Midi beats (sixteenth notes) since the start of the song.
+    SongPosition(U14),
+    SongSelect(U7),
+    TuneRequest,
+}
+/// Decodes a system common message.
+///
+/// Returns an `Error::InvalidMessage` if the status byte does not denote a system common message
+/// or one of the used data bytes has its most significant bit set.
+impl TryFrom<MidiMessage> for SystemCommon {
+    type Error = Error;
+
+    fn try_from(msg: MidiMessage) -> Result<Self> {
+        let data1 = U7::new(msg.data1).ok_or(Error::InvalidMessage);
+        let data2 = U7::new(msg.data2).ok_or(Error::InvalidMessage);
+        match msg.status {
+            MTC_QUARTER_FRAME => {
+                let data = data1?.0;
+                Ok(SystemCommon::MtcQuarterFrame {
+                    message_type: data >> 4,
+                    value: data & 0x0F,
+                })
+            }
+            SONG_POSITION => Ok(SystemCommon::SongPosition(U14::from_halves(data1?, data2?))),
+            SONG_SELECT => Ok(SystemCommon::SongSelect(data1?)),
+            TUNE_REQUEST => Ok(SystemCommon::TuneRequest),
+            _ => Err(Error::InvalidMessage),
+        }
+    }
+}
+impl From<SystemCommon> for MidiMessage {
+    fn from(msg: SystemCommon) -> Self {
+        let (status, data1, data2) = match msg {
+            SystemCommon::MtcQuarterFrame {
+                message_type,
+                value,
+            } => (
+                MTC_QUARTER_FRAME,
+                (message_type & 0x07) << 4 | value & 0x0F,
+                0,
+            ),
+            SystemCommon::SongPosition(position) => {
+                (SONG_POSITION, position.lsb().0, position.msb().0)
+            }
+            SystemCommon::SongSelect(song) => (SONG_SELECT, song.0, 0),
+            SystemCommon::TuneRequest => (TUNE_REQUEST, 0, 0),
+        };
+        MidiMessage {
+            status,
+            data1,
+            data2,
+            data3: 0,
+        }
+    }
+}
+impl From<SystemCommon> for MidiEvent {
+    fn from(msg: SystemCommon) -> Self {
+        MidiEvent::from(MidiMessage::from(msg))
+    }
+}
+
+/// A decoded Midi system realtime message.
+///
+/// Realtime messages consist of a single status byte and may be sent at any time,
+/// even in the middle of a SysEx message.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SystemRealtime {
+    TimingClock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    SystemReset,
+}
+impl SystemRealtime {
+    /// Returns the realtime message for the given status byte or `None` if the
+    /// byte does not denote a realtime message.
+    pub fn from_status(status: u8) -> Option<Self> {
+        match status {
+            TIMING_CLOCK => Some(SystemRealtime::TimingClock),
+            START => Some(SystemRealtime::Start),
+            CONTINUE => Some(SystemRealtime::Continue),
+            STOP => Some(SystemRealtime::Stop),
+            ACTIVE_SENSING => Some(SystemRealtime::ActiveSensing),
+            SYSTEM_RESET => Some(SystemRealtime::SystemReset),
+            _ => None,
+        }
+    }
+
+    /// Returns the status byte of the message.
+    pub fn status(self) -> u8 {
+        match self {
+            SystemRealtime::TimingClock => TIMING_CLOCK,
+            SystemRealtime::Start => START,
+            SystemRealtime::Continue => CONTINUE,
+            SystemRealtime::Stop => STOP,
+            SystemRealtime::ActiveSensing => ACTIVE_SENSING,
+            SystemRealtime::SystemReset => SYSTEM_RESET,
+        }
+    }
+}
+/// Decodes a system realtime message, the data bytes are ignored.
+///
+/// Returns an `Error::InvalidMessage` if the status byte does not denote a realtime message.
+impl TryFrom<MidiMessage> for SystemRealtime {
+    type Error = Error;
+
+    fn try_from(msg: MidiMessage) -> Result<Self> {
+        SystemRealtime::from_status(msg.status).ok_or(Error::InvalidMessage)
+    }
+}
+impl From<SystemRealtime> for MidiMessage {
+    fn from(msg: SystemRealtime) -> Self {
+        MidiMessage {
+            status: msg.status(),
+            data1: 0,
+            data2: 0,
+            data3: 0,
+        }
+    }
+}
+impl From<SystemRealtime> for MidiEvent {
+    fn from(msg: SystemRealtime) -> Self {
+        MidiEvent::from(MidiMessage::from(msg))
+    }
+}
+
+/// Any decoded Midi message that fits into a single `MidiMessage`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Message {
+    Channel(ChannelMessage),
+    Common(SystemCommon),
+    Realtime(SystemRealtime),
+}
+/// Decodes a channel voice, system common or system realtime message.
+///
+/// Returns an `Error::InvalidMessage` for SysEx, undefined status bytes or malformed data bytes.
+impl TryFrom<MidiMessage> for Message {
+    type Error = Error;
+
+    fn try_from(msg: MidiMessage) -> Result<Self> {
+        match msg.status {
+            0x80..=0xEF => ChannelMessage::try_from(msg).map(Message::Channel),
+            0xF8..=0xFF => SystemRealtime::try_from(msg).map(Message::Realtime),
+            _ => SystemCommon::try_from(msg).map(Message::Common),
+        }
+    }
+}
+impl From<Message> for MidiMessage {
+    fn from(msg: Message) -> Self {
+        match msg {
+            Message::Channel(msg) => MidiMessage::from(msg),
+            Message::Common(msg) => MidiMessage::from(msg),
+            Message::Realtime(msg) => MidiMessage::from(msg),
+        }
+    }
+}
+impl From<Message> for MidiEvent {
+    fn from(msg: Message) -> Self {
+        MidiEvent::from(MidiMessage::from(msg))
+    }
+}
+impl From<ChannelMessage> for Message {
+    fn from(msg: ChannelMessage) -> Self {
+        Message::Channel(msg)
+    }
+}
+impl From<SystemCommon> for Message {
+    fn from(msg: SystemCommon) -> Self {
+        Message::Common(msg)
+    }
+}
+impl From<SystemRealtime> for Message {
+    fn from(msg: SystemRealtime) -> Self {
+        Message::Realtime(msg)
+    }
+}
+
+/// Returns the bytes of a message that is not part of a SysEx message, without the
+/// unused data bytes.
+pub(crate) fn message_bytes(msg: MidiMessage) -> Vec<u8> {
+    let len = match msg.status & 0xF0 {
+        PROGRAM_CHANGE | CHANNEL_PRESSURE => 2,
+        NOTE_OFF | NOTE_ON | POLY_AFTERTOUCH | CONTROL_CHANGE | PITCH_BEND => 3,
+        _ => match msg.status {
+            SONG_POSITION => 3,
+            MTC_QUARTER_FRAME | SONG_SELECT => 2,
+            _ => 1,
+        },
+    };
+    [msg.status, msg.data1, msg.data2][..len].to_vec()
+}
@@ -0,0 +1,402 @@
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::mem;
+use std::ops::Range;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use ffi;
+use io::OutputPort;
+use message::{self, Channel, ChannelMessage, U7};
+use smf::{absolute_ticks, EventKind, Format, Smf, TempoMap};
+use sysex::{InputEvent, SysExAssembler};
+use types::{Error, MidiEvent, MidiMessage, Result};
+
+/// The controller number of the All Notes Off channel mode message.
+const ALL_NOTES_OFF: u8 = 123;
+
+/// A message of the sequence, with its time in microseconds since the start.
+#[derive(Clone, Debug)]
+struct ScheduledEvent {
+    micros: u64,
+    message: Message,
+}
+
+#[derive(Clone, Debug)]
+enum Message {
+    Short(MidiMessage),
+    SysEx(Vec<u8>),
+}
+
+/// The transport state, shared between `play` and the controlling methods.
+struct Transport {
+    playing: bool,
+    /// The position in microseconds at `anchor`, or while stopped
+    position: u64,
+    anchor: Instant,
+    tempo_scale: f64,
+    loop_range: Option<Range<u64>>,
+    /// Set by `seek` and at the loop end, so `play` continues at the new position
+    jumped: bool,
+}
+impl Transport {
+    fn position_at(&self, now: Instant) -> u64 {
+        if self.playing {
+            let elapsed = (now - self.anchor).as_micros() as f64 * self.tempo_scale;
+            self.position + elapsed as u64
+        } else {
+            self.position
+        }
+    }
+
+    /// Sets the position and restarts the elapsed time.
+    fn set_position(&mut self, position: u64, now: Instant) {
+        self.position = position;
+        self.anchor = now;
+    }
+}
+
+/// Plays a sequence of Midi messages through an `OutputPort`.
+///
+/// The sequence is created from a Standard Midi File, which is converted to time using its
+/// tempo map, or from `MidiEvent`s with timestamps in milliseconds. Messages are sent at their
+/// time when they are due, so the port's latency is not used.
+///
+/// `play` blocks until the end of the sequence or until `stop` is called, the other methods
+/// can be called from other threads while playing, e.g. by sharing the player in an `Arc`.
+pub struct Player {
+    events: Vec<ScheduledEvent>,
+    tempo_map: TempoMap,
+    transport: Mutex<Transport>,
+    changed: Condvar,
+}
+impl Player {
+    /// Creates a player for a format 0 or format 1 file.
+    ///
+    /// SysEx messages that are split into several events and meta events are skipped,
+    /// escaped bytes are sent if they are a single system message.
+    /// Returns an `Error::InvalidSmf(_)` for a format 2 file.
+    pub fn from_smf(smf: &Smf) -> Result<Player> {
+        if smf.format == Format::Sequential {
+            return Err(Error::InvalidSmf("format 2 files can not be played"));
+        }
+        let tempo_map = TempoMap::from_smf(smf);
+        let mut events = Vec::new();
+        for track in &smf.tracks {
+            for (tick, kind) in absolute_ticks(track) {
+                let message = match *kind {
+                    EventKind::Midi(msg) => Message::Short(msg.into()),
+                    EventKind::SysEx(ref data) if data.last() == Some(&ffi::MIDI_EOX) => {
+                        Message::SysEx(data.clone())
+                    }
+                    EventKind::Escape(ref bytes) => match system_message(bytes) {
+                        Some(msg) => Message::Short(msg),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                events.push(ScheduledEvent {
+                    micros: tempo_map.tick_to_micros(tick),
+                    message,
+                });
+            }
+        }
+        Ok(Player::new(events, tempo_map))
+    }
+
+    /// Creates a player for events with timestamps in milliseconds since the start,
+    /// using a tempo map of 1000 ticks per quarter note at 60 bpm, so a tick is a millisecond.
+    ///
+    /// SysEx messages split over several events, e.g. as read from an `InputPort`, are
+    /// reassembled and sent at the timestamp of their first part. Incomplete ones are skipped.
+    pub fn from_events(events: &[MidiEvent]) -> Player {
+        let events = SysExAssembler::new()
+            .feed(events.iter().cloned())
+            .into_iter()
+            .filter_map(|event| match event {
+                Ok(InputEvent::Midi(event)) => Some(ScheduledEvent {
+                    micros: u64::from(event.timestamp) * 1000,
+                    message: Message::Short(event.message),
+                }),
+                Ok(InputEvent::SysEx(sysex)) => Some(ScheduledEvent {
+                    micros: u64::from(sysex.timestamp) * 1000,
+                    message: Message::SysEx(sysex.data),
+                }),
+                Err(_) => None,
+            })
+            .collect();
+        Player::new(events, TempoMap::with_tempo(1000, 1_000_000))
+    }
+
+    fn new(mut events: Vec<ScheduledEvent>, tempo_map: TempoMap) -> Player {
+        // stable, so simultaneous events keep the order of their tracks
+        events.sort_by_key(|event| event.micros);
+        Player {
+            events,
+            tempo_map,
+            transport: Mutex::new(Transport {
+                playing: false,
+                position: 0,
+                anchor: Instant::now(),
+                tempo_scale: 1.0,
+                loop_range: None,
+                jumped: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Returns the tempo map, e.g. to convert ticks to positions for `seek`.
+    pub fn tempo_map(&self) -> &TempoMap {
+        &self.tempo_map
+    }
+
+    /// Returns the time of the last message at the original tempo.
+    pub fn duration(&self) -> Duration {
+        Duration::from_micros(self.events.last().map_or(0, |event| event.micros))
+    }
+
+    /// Returns `true` while `play` is running.
+    pub fn is_playing(&self) -> bool {
+        self.transport().playing
+    }
+
+    /// Returns the current position at the original tempo.
+    pub fn position(&self) -> Duration {
+        Duration::from_micros(self.transport().position_at(Instant::now()))
+    }
+
+    /// Moves to the given position at the original tempo, sounding notes are stopped.
+    pub fn seek(&self, position: Duration) {
+        let mut transport = self.transport();
+        transport.set_position(position.as_micros() as u64, Instant::now());
+        transport.jumped = true;
+        self.changed.notify_all();
+    }
+
+    /// Returns the factor the tempo is multiplied with.
+    pub fn tempo_scale(&self) -> f64 {
+        self.transport().tempo_scale
+    }
+
+    /// Multiplies the tempo of the sequence with `scale`, e.g. `2.0` plays twice as fast.
+    /// Values that are not positive and finite are ignored.
+    pub fn set_tempo_scale(&self, scale: f64) {
+        if !(scale.is_finite() && scale > 0.0) {
+            return;
+        }
+        let mut transport = self.transport();
+        let now = Instant::now();
+        let position = transport.position_at(now);
+        transport.set_position(position, now);
+        transport.tempo_scale = scale;
+        self.changed.notify_all();
+    }
+
+    /// Repeats the given range of positions, `None` plays to the end.
+    ///
+    /// If the current position is after the end of the range, playing continues at its start.
+    /// An empty range disables looping like `None`.
+    pub fn set_loop(&self, range: Option<Range<Duration>>) {
+        let range = range
+            .map(|r| r.start.as_micros() as u64..r.end.as_micros() as u64)
+            .filter(|r| r.start < r.end);
+        let mut transport = self.transport();
+        transport.loop_range = range;
+        self.changed.notify_all();
+    }
+
+    /// Stops `play`, the position is kept so a following `play` continues from there.
+    pub fn stop(&self) {
+        let mut transport = self.transport();
+        let now = Instant::now();
+        let position = transport.position_at(now);
+        transport.set_position(position, now);
+        transport.playing = false;
+        self.changed.notify_all();
+    }
+
+    /// Plays the sequence from the current position until its end or until `stop` is called.
+    ///
+    /// When the end is reached the position is set to the start. Notes that are still
+    /// sounding when playing stops get a note off and every used channel an All Notes Off.
+    /// Returns an `Error::AlreadyPlaying` if the player is already playing.
+    pub fn play(&self, port: &mut OutputPort) -> Result<()> {
+        {
+            let mut transport = self.transport();
+            if transport.playing {
+                return Err(Error::AlreadyPlaying);
+            }
+            transport.playing = true;
+            transport.jumped = false;
+            let position = transport.position;
+            transport.set_position(position, Instant::now());
+        }
+        let mut notes = SoundingNotes::default();
+        let result = self.run(port, &mut notes);
+        let silenced = notes.silence(port, true);
+        let mut transport = self.transport();
+        if transport.playing {
+            // stopped by an error, keep the position
+            let now = Instant::now();
+            let position = transport.position_at(now);
+            transport.set_position(position, now);
+            transport.playing = false;
+        }
+        result.and(silenced)
+    }
+
+    fn run(&self, port: &mut OutputPort, notes: &mut SoundingNotes) -> Result<()> {
+        let mut transport = self.transport();
+        let mut next = self.first_event_at(transport.position);
+        loop {
+            if !transport.playing {
+                return Ok(());
+            }
+            let now = Instant::now();
+            let mut position = transport.position_at(now);
+            let loop_start = transport
+                .loop_range
+                .as_ref()
+                .filter(|range| position >= range.end)
+                .map(|range| range.start);
+            if let Some(start) = loop_start {
+                transport.set_position(start, now);
+                transport.jumped = true;
+            }
+            if transport.jumped {
+                transport.jumped = false;
+                position = transport.position;
+                next = self.first_event_at(position);
+                drop(transport);
+                notes.silence(port, false)?;
+                transport = self.transport();
+                continue;
+            }
+
+            // the loop end or the end of the sequence
+            let end = transport
+                .loop_range
+                .as_ref()
+                .map_or(u64::MAX, |range| range.end);
+            let due = self.events[next..]
+                .iter()
+                .take_while(|event| event.micros <= position && event.micros < end)
+                .count();
+            if due > 0 {
+                drop(transport);
+                for event in &self.events[next..next + due] {
+                    notes.send(port, &event.message)?;
+                }
+                next += due;
+                transport = self.transport();
+                continue;
+            }
+
+            let target = match self.events.get(next) {
+                Some(event) if event.micros < end => event.micros,
+                _ if end != u64::MAX => end,
+                _ => {
+                    // finished, start from the beginning next time
+                    transport.set_position(0, now);
+                    transport.playing = false;
+                    return Ok(());
+                }
+            };
+            let wait = (target - position) as f64 / transport.tempo_scale;
+            transport = self
+                .changed
+                .wait_timeout(transport, Duration::from_micros(wait.ceil() as u64))
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+        }
+    }
+
+    /// Returns the index of the first event at or after `position`.
+    fn first_event_at(&self, position: u64) -> usize {
+        self.events.partition_point(|event| event.micros < position)
+    }
+
+    fn transport(&self) -> MutexGuard<'_, Transport> {
+        self.transport
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Tracks the notes that are sounding and the channels that were used.
+#[derive(Default)]
+struct SoundingNotes {
+    notes: BTreeSet<(Channel, U7)>,
+    channels: BTreeSet<Channel>,
+}
+impl SoundingNotes {
+    fn send(&mut self, port: &mut OutputPort, message: &Message) -> Result<()> {
+        match *message {
+            Message::Short(msg) => {
+                if let Ok(channel_msg) = ChannelMessage::try_from(msg) {
+                    self.channels.insert(channel_msg.channel());
+                    match channel_msg {
+                        ChannelMessage::NoteOn {
+                            channel,
+                            note,
+                            velocity,
+                        } if velocity.value() > 0 => {
+                            self.notes.insert((channel, note));
+                        }
+                        ChannelMessage::NoteOn { channel, note, .. }
+                        | ChannelMessage::NoteOff { channel, note, .. } => {
+                            self.notes.remove(&(channel, note));
+                        }
+                        _ => (),
+                    }
+                }
+                port.write_message(msg)
+            }
+            Message::SysEx(ref data) => port.write_sysex(0, data),
+        }
+    }
+
+    /// Sends a note off for every sounding note and an All Notes Off to
+    /// every used channel if `all_notes_off` is set.
+    fn silence(&mut self, port: &mut OutputPort, all_notes_off: bool) -> Result<()> {
+        for (channel, note) in mem::take(&mut self.notes) {
+            port.write_message(ChannelMessage::NoteOff {
+                channel,
+                note,
+                velocity: U7::from_masked(0),
+            })?;
+        }
+        if all_notes_off {
+            for channel in mem::take(&mut self.channels) {
+                port.write_message(ChannelMessage::ControlChange {
+                    channel,
+                    controller: U7::from_masked(ALL_NOTES_OFF),
+                    value: U7::from_masked(0),
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes escaped bytes that form a single system common or realtime message.
+fn system_message(bytes: &[u8]) -> Option<MidiMessage> {
+    match *bytes {
+        [0xF1..=0xF6, ..] | [0xF8..=0xFF, ..] if bytes.len() <= 3 => {
+            let msg = MidiMessage {
+                status: bytes[0],
+                data1: bytes.get(1).cloned().unwrap_or(0),
+                data2: bytes.get(2).cloned().unwrap_or(0),
+                data3: 0,
+            };
+            let valid = bytes[1..].iter().all(|&byte| byte < 0x80);
+            if valid && message::message_bytes(msg) == bytes {
+                Some(msg)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,170 @@
+use context::PortMidi;
+use device::DeviceInfo;
+use types::{Error, Result};
+
+#[derive(Clone, Debug)]
+enum NameMatch {
+    Exact(String),
+    Substring(String),
+    Pattern(String),
+}
+
+/// Selects devices by name, interface and direction.
+///
+/// All given criteria have to match, e.g. `DeviceQuery::new().name_contains("nano").input()`
+/// selects the input devices with *nano* in their name.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceQuery {
+    name: Option<NameMatch>,
+    ignore_case: bool,
+    interface: Option<String>,
+    input: bool,
+    output: bool,
+}
+impl DeviceQuery {
+    /// Creates a query that matches all devices.
+    pub fn new() -> Self {
+        DeviceQuery::default()
+    }
+
+    /// Matches devices with exactly the given name.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(NameMatch::Exact(name.to_owned()));
+        self
+    }
+
+    /// Matches devices whose name contains the given string.
+    pub fn name_contains(mut self, part: &str) -> Self {
+        self.name = Some(NameMatch::Substring(part.to_owned()));
+        self
+    }
+
+    /// Matches devices whose name matches the given pattern.
+    ///
+    /// The pattern supports a subset of the regular expression syntax: `.` matches
+    /// any character, `*`, `+` and `?` repeat the preceding character, `^` and `$`
+    /// anchor the pattern at the start and end of the name and `\` escapes the
+    /// following character. Without anchors the pattern may match anywhere in the name.
+    pub fn name_matches(mut self, pattern: &str) -> Self {
+        self.name = Some(NameMatch::Pattern(pattern.to_owned()));
+        self
+    }
+
+    /// Compares names case-insensitively.
+    pub fn ignore_case(mut self) -> Self {
+        self.ignore_case = true;
+        self
+    }
+
+    /// Matches devices of the given underlying Midi API, e.g. `ALSA`, compared case-insensitively.
+    pub fn interface(mut self, interface: &str) -> Self {
+        self.interface = Some(interface.to_owned());
+        self
+    }
+
+    /// Matches devices that support input.
+    pub fn input(mut self) -> Self {
+        self.input = true;
+        self
+    }
+
+    /// Matches devices that support output.
+    pub fn output(mut self) -> Self {
+        self.output = true;
+        self
+    }
+
+    /// Returns `true` if the given device matches all criteria of the query.
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        if (self.input && !device.is_input()) || (self.output && !device.is_output()) {
+            return false;
+        }
+        if let Some(ref interface) = self.interface {
+            if !device.interface().eq_ignore_ascii_case(interface) {
+                return false;
+            }
+        }
+        let fold = |s: &str| {
+            if self.ignore_case {
+                s.to_lowercase()
+            } else {
+                s.to_owned()
+            }
+        };
+        let name = fold(device.name());
+        match self.name {
+            None => true,
+            Some(NameMatch::Exact(ref expected)) => name == fold(expected),
+            Some(NameMatch::Substring(ref part)) => name.contains(&fold(part)),
+            Some(NameMatch::Pattern(ref pattern)) => {
+                let pattern = fold(pattern).chars().collect::<Vec<_>>();
+                let name = name.chars().collect::<Vec<_>>();
+                pattern_matches(&pattern, &name)
+            }
+        }
+    }
+
+    /// Returns all devices of the given context that match the query.
+    pub fn find_all(&self, context: &PortMidi) -> Result<Vec<DeviceInfo>> {
+        Ok(context
+            .devices()?
+            .into_iter()
+            .filter(|device| self.matches(device))
+            .collect())
+    }
+
+    /// Returns the single device of the given context that matches the query.
+    ///
+    /// Returns an `Error::NoMatchingDevice` if no device matches and an
+    /// `Error::AmbiguousDevice(_)` with the matching devices if more than one device matches.
+    pub fn find(&self, context: &PortMidi) -> Result<DeviceInfo> {
+        let mut devices = self.find_all(context)?;
+        match devices.len() {
+            0 => Err(Error::NoMatchingDevice),
+            1 => Ok(devices.remove(0)),
+            _ => Err(Error::AmbiguousDevice(devices)),
+        }
+    }
+}
+
+/// Returns `true` if the pattern matches anywhere in `text`.
+fn pattern_matches(pattern: &[char], text: &[char]) -> bool {
+    if let Some((&'^', rest)) = pattern.split_first() {
+        return match_here(rest, text);
+    }
+    (0..=text.len()).any(|start| match_here(pattern, &text[start..]))
+}
+
+/// Returns `true` if the pattern matches at the start of `text`.
+fn match_here(pattern: &[char], text: &[char]) -> bool {
+    let (atom, rest) = match pattern.split_first() {
+        None => return true,
+        Some((&'$', &[])) => return text.is_empty(),
+        Some((&'\\', rest)) if !rest.is_empty() => (Some(rest[0]), &rest[1..]),
+        Some((&'.', rest)) => (None, rest),
+        Some((&c, rest)) => (Some(c), rest),
+    };
+    let atom_matches = |c: &char| atom.is_none_or(|a| a == *c);
+    match rest.split_first() {
+        Some((&'*', rest)) => match_repeat(&atom_matches, 0, rest, text),
+        Some((&'+', rest)) => match_repeat(&atom_matches, 1, rest, text),
+        Some((&'?', rest)) => {
+            (text.first().is_some_and(&atom_matches) && match_here(rest, &text[1..]))
+                || match_here(rest, text)
+        }
+        _ => text.first().is_some_and(&atom_matches) && match_here(rest, &text[1..]),
+    }
+}
+
+/// Matches at least `min` repetitions of an atom followed by the rest of the pattern.
+fn match_repeat<F: Fn(&char) -> bool>(
+    atom_matches: &F,
+    min: usize,
+    pattern: &[char],
+    text: &[char],
+) -> bool {
+    let max = text.iter().take_while(|c| atom_matches(c)).count();
+    (min..=max)
+        .rev()
+        .any(|cnt| match_here(pattern, &text[cnt..]))
+}
@@ -0,0 +1,242 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use ffi;
+use io::InputPort;
+use message;
+use smf::{Format, Smf, SmfWriter, TempoMap};
+use sysex::{InputEvent, SysExAssembler};
+use time::TimeSource;
+use types::{Error, MidiEvent, Result};
+
+/// The state of a `Recorder`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RecorderState {
+    Stopped,
+    Recording,
+    Paused,
+}
+
+/// An event of a take, see `Recorder::events`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RecordedEvent {
+    /// Milliseconds since the start of the take, without the paused time
+    pub time: u32,
+    /// The event with its original PortMidi timestamp
+    pub event: InputEvent,
+}
+
+/// A time span in which events are recorded, `end` is `None` while recording.
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    start: ffi::PmTimestamp,
+    end: Option<ffi::PmTimestamp>,
+}
+
+/// Records the events of an `InputPort` into a take that can be saved as a
+/// Standard Midi File or a text log.
+///
+/// Events are assigned to the take by their timestamps, so events that are read
+/// after `pause` or `stop` are still recorded if they were received before.
+/// The `TimeSource` must be the one that timestamps the port's events, e.g. a `Clock`
+/// for ports opened without a time source.
+pub struct Recorder {
+    time_source: Arc<dyn TimeSource>,
+    state: RecorderState,
+    segments: Vec<Segment>,
+    punch_ranges: Vec<Range<u32>>,
+    assembler: SysExAssembler,
+    events: Vec<RecordedEvent>,
+}
+impl Recorder {
+    /// Creates a stopped recorder.
+    pub fn new(time_source: Arc<dyn TimeSource>) -> Self {
+        Recorder {
+            time_source,
+            state: RecorderState::Stopped,
+            segments: Vec::new(),
+            punch_ranges: Vec::new(),
+            assembler: SysExAssembler::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> RecorderState {
+        self.state
+    }
+
+    /// Starts a new take, discarding the previous one, or resumes a paused take.
+    pub fn start(&mut self) {
+        let now = self.time_source.now();
+        match self.state {
+            RecorderState::Recording => return,
+            RecorderState::Stopped => {
+                self.segments.clear();
+                self.events.clear();
+                self.assembler = SysExAssembler::new();
+            }
+            RecorderState::Paused => (),
+        }
+        self.segments.push(Segment {
+            start: now,
+            end: None,
+        });
+        self.state = RecorderState::Recording;
+    }
+
+    /// Pauses recording, the paused time is left out of the take.
+    pub fn pause(&mut self) {
+        if self.state == RecorderState::Recording {
+            self.end_segment();
+            self.state = RecorderState::Paused;
+        }
+    }
+
+    /// Stops recording, the take is kept until the next `start`.
+    pub fn stop(&mut self) {
+        if self.state == RecorderState::Recording {
+            self.end_segment();
+        }
+        self.state = RecorderState::Stopped;
+    }
+
+    /// Only keeps events in the given range of milliseconds of the take, in addition to
+    /// the ranges added before. Without punch ranges all events are kept.
+    pub fn add_punch_range(&mut self, range: Range<u32>) {
+        self.punch_ranges.push(range);
+    }
+
+    /// Removes all punch ranges.
+    pub fn clear_punch_ranges(&mut self) {
+        self.punch_ranges.clear();
+    }
+
+    /// Reads all available events of the port and returns the number of recorded events.
+    ///
+    /// Events outside of the take and incomplete SysEx messages are dropped.
+    pub fn record(&mut self, port: &InputPort) -> Result<usize> {
+        let mut cnt = 0;
+        while let Some(events) = port.read_n(1024)? {
+            for event in events {
+                cnt += self.push(event);
+            }
+        }
+        Ok(cnt)
+    }
+
+    /// Records a single event, e.g. from a `Listener` callback, and returns
+    /// the number of recorded events.
+    ///
+    /// SysEx parts are collected until the message is complete, so a single event
+    /// can complete a SysEx message and record an interleaved realtime message.
+    pub fn push(&mut self, event: MidiEvent) -> usize {
+        let mut cnt = 0;
+        for event in self
+            .assembler
+            .push(event)
+            .into_iter()
+            .filter_map(|res| res.ok())
+        {
+            let timestamp = match event {
+                InputEvent::Midi(ref midi) => midi.timestamp,
+                InputEvent::SysEx(ref sysex) => sysex.timestamp,
+            };
+            if let Some(time) = self.take_time(timestamp) {
+                if self.punch_ranges.is_empty()
+                    || self.punch_ranges.iter().any(|r| r.contains(&time))
+                {
+                    self.events.push(RecordedEvent { time, event });
+                    cnt += 1;
+                }
+            }
+        }
+        cnt
+    }
+
+    /// Returns the events of the take.
+    pub fn events(&self) -> &[RecordedEvent] {
+        &self.events
+    }
+
+    /// Converts the take into a format 0 or format 1 file with a single track.
+    pub fn to_smf(&self, tempo_map: TempoMap, format: Format) -> Result<Smf> {
+        let mut writer = SmfWriter::new(tempo_map);
+        let track = writer.add_track(Some("Recording"));
+        for recorded in &self.events {
+            match recorded.event {
+                InputEvent::Midi(event) => {
+                    let event = MidiEvent {
+                        message: event.message,
+                        timestamp: recorded.time,
+                    };
+                    writer.add_midi_events(track, &[event])?;
+                }
+                InputEvent::SysEx(ref sysex) => {
+                    writer.add_sysex(track, recorded.time, &sysex.data)?;
+                }
+            }
+        }
+        writer.to_smf(format)
+    }
+
+    /// Saves the take as a format 1 Standard Midi File.
+    pub fn save_smf<P: AsRef<Path>>(&self, path: P, tempo_map: TempoMap) -> Result<()> {
+        self.to_smf(tempo_map, Format::Parallel)?.save(path)
+    }
+
+    /// Writes the take as text, one event per line with the time in the take,
+    /// the PortMidi timestamp and the message bytes in hex, separated by tabs.
+    pub fn write_log<W: Write>(&self, mut out: W) -> Result<()> {
+        self.write_lines(&mut out).map_err(Error::from)
+    }
+
+    /// Saves the take as a text log, see `write_log`.
+    pub fn save_log<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path)?;
+        self.write_log(BufWriter::new(file))
+    }
+
+    fn write_lines<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "# time\ttimestamp\tbytes")?;
+        for recorded in &self.events {
+            let (timestamp, bytes) = match recorded.event {
+                InputEvent::Midi(event) => (event.timestamp, message::message_bytes(event.message)),
+                InputEvent::SysEx(ref sysex) => (sysex.timestamp, sysex.data.clone()),
+            };
+            let bytes = bytes
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(out, "{}\t{}\t{}", recorded.time, timestamp, bytes)?;
+        }
+        out.flush()
+    }
+
+    fn end_segment(&mut self) {
+        let now = self.time_source.now();
+        if let Some(segment) = self.segments.last_mut() {
+            segment.end = Some(now);
+        }
+    }
+
+    /// Returns the time in the take for a timestamp, `None` if it is not in a recorded segment.
+    fn take_time(&self, timestamp: ffi::PmTimestamp) -> Option<u32> {
+        let mut offset = 0;
+        for segment in &self.segments {
+            let end = segment.end.unwrap_or(ffi::PmTimestamp::MAX);
+            if timestamp < segment.start {
+                return None;
+            }
+            if timestamp < end {
+                return Some(offset + (timestamp - segment.start));
+            }
+            offset += end - segment.start;
+        }
+        None
+    }
+}
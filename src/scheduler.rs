@@ -0,0 +1,292 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::hint;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use context::PortMidi;
+use device::DeviceInfo;
+use ffi;
+use types::{Error, MidiMessage, Result};
+
+/// The time before the due time of an event in which the scheduler busy-waits
+/// instead of sleeping, see `Scheduler::with_spin_threshold`.
+pub const DEFAULT_SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+/// Statistics of the delays between the due times of events and the times they were written.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct JitterStats {
+    /// The number of written events
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    /// The sum of all delays
+    pub total: Duration,
+}
+impl JitterStats {
+    /// Returns the average delay.
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_nanos((self.total.as_nanos() / u128::from(self.count)) as u64)
+        }
+    }
+
+    fn add(&mut self, delay: Duration) {
+        self.min = if self.count == 0 {
+            delay
+        } else {
+            self.min.min(delay)
+        };
+        self.max = self.max.max(delay);
+        self.total += delay;
+        self.count += 1;
+    }
+}
+
+enum Message {
+    Short(MidiMessage),
+    SysEx(Vec<u8>),
+}
+
+/// A queued message, ordered by due time and then by the order of scheduling.
+struct Entry {
+    due: Instant,
+    seq: u64,
+    message: Message,
+}
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Entry {}
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+struct Queue {
+    entries: BinaryHeap<Reverse<Entry>>,
+    next_seq: u64,
+    running: bool,
+    jitter: JitterStats,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+}
+impl Shared {
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Handle of a background thread that writes scheduled messages to an `OutputPort`
+/// at their due times.
+///
+/// The thread sleeps until shortly before the next due time and busy-waits for the rest,
+/// which gives a sub-millisecond accuracy without using the latency of the port.
+/// The delays are collected in `JitterStats`.
+///
+/// The thread is stopped and joined when the handle is dropped, pending messages are discarded.
+pub struct Scheduler {
+    device: DeviceInfo,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+impl Scheduler {
+    /// Opens an output port for the given device and buffer size and starts a thread
+    /// that writes the scheduled messages to it, using the `DEFAULT_SPIN_THRESHOLD`.
+    ///
+    /// Returns an error if the port could not be opened.
+    pub fn new(
+        context: Arc<PortMidi>,
+        device: DeviceInfo,
+        buffer_size: usize,
+    ) -> Result<Scheduler> {
+        Scheduler::with_spin_threshold(context, device, buffer_size, DEFAULT_SPIN_THRESHOLD)
+    }
+
+    /// Like `Scheduler::new`, but busy-waits for the last `spin_threshold` before a due time.
+    ///
+    /// A longer threshold compensates for a coarser sleep resolution of the system at
+    /// the cost of CPU time.
+    pub fn with_spin_threshold(
+        context: Arc<PortMidi>,
+        device: DeviceInfo,
+        buffer_size: usize,
+        spin_threshold: Duration,
+    ) -> Result<Scheduler> {
+        let mut port = context.owned_output_port(device.clone(), buffer_size)?;
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                entries: BinaryHeap::new(),
+                next_seq: 0,
+                running: true,
+                jitter: JitterStats::default(),
+            }),
+            changed: Condvar::new(),
+        });
+        let thread = thread::spawn({
+            let shared = shared.clone();
+            move || {
+                let result = dispatch(&shared, spin_threshold, |message| match message {
+                    Message::Short(msg) => port.write_message(msg),
+                    Message::SysEx(data) => port.write_sysex(0, &data),
+                });
+                shared.queue().running = false;
+                result
+            }
+        });
+        Ok(Scheduler {
+            device,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Schedules a message to be written at `due`, messages that are already due are
+    /// written immediately. Messages with the same due time are written in the order
+    /// they were scheduled.
+    pub fn schedule<T: Into<MidiMessage>>(&self, due: Instant, message: T) {
+        self.push(due, Message::Short(message.into()));
+    }
+
+    /// Schedules an EOX-terminated SysEx message to be written at `due`.
+    /// Returns an `Error::MissingEox` if `msg` does not end with EOX.
+    pub fn schedule_sysex(&self, due: Instant, msg: &[u8]) -> Result<()> {
+        if Some(&ffi::MIDI_EOX) != msg.last() {
+            return Err(Error::MissingEox);
+        }
+        self.push(due, Message::SysEx(msg.to_vec()));
+        Ok(())
+    }
+
+    /// Returns the number of messages that have not been written yet.
+    pub fn pending(&self) -> usize {
+        self.shared.queue().entries.len()
+    }
+
+    /// Discards all messages that have not been written yet.
+    pub fn clear(&self) {
+        self.shared.queue().entries.clear();
+        self.shared.changed.notify_all();
+    }
+
+    /// Returns the statistics of the written messages.
+    pub fn jitter(&self) -> JitterStats {
+        self.shared.queue().jitter
+    }
+
+    /// Resets the statistics, e.g. after a warm-up phase.
+    pub fn reset_jitter(&self) {
+        self.shared.queue().jitter = JitterStats::default();
+    }
+
+    /// Returns the `DeviceInfo` of the Midi device the scheduler writes to.
+    pub fn device(&self) -> DeviceInfo {
+        self.device.clone()
+    }
+
+    /// Returns `false` if the scheduler thread has stopped because of a write error.
+    pub fn is_running(&self) -> bool {
+        self.shared.queue().running && self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    /// Stops the scheduler thread, waits for it to finish and returns the
+    /// write error that stopped it, if any.
+    pub fn stop(mut self) -> Result<()> {
+        self.join()
+    }
+
+    fn push(&self, due: Instant, message: Message) {
+        let mut queue = self.shared.queue();
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.entries.push(Reverse(Entry { due, seq, message }));
+        self.shared.changed.notify_all();
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.shared.queue().running = false;
+        self.shared.changed.notify_all();
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or(Err(Error::Unknown)),
+            None => Ok(()),
+        }
+    }
+}
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+/// Writes the queued messages at their due times until the scheduler is stopped.
+fn dispatch<F>(shared: &Shared, spin_threshold: Duration, mut write: F) -> Result<()>
+where
+    F: FnMut(Message) -> Result<()>,
+{
+    let mut queue = shared.queue();
+    loop {
+        if !queue.running {
+            return Ok(());
+        }
+        let due = match queue.entries.peek() {
+            Some(entry) => entry.0.due,
+            None => {
+                queue = shared
+                    .changed
+                    .wait(queue)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                continue;
+            }
+        };
+        let now = Instant::now();
+        if due > now + spin_threshold {
+            // an earlier message or `stop` wakes the thread up
+            queue = shared
+                .changed
+                .wait_timeout(queue, due - now - spin_threshold)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+            continue;
+        }
+        if due > now {
+            drop(queue);
+            while Instant::now() < due {
+                hint::spin_loop();
+            }
+            queue = shared.queue();
+            continue;
+        }
+
+        let mut entries = Vec::new();
+        while queue.entries.peek().is_some_and(|entry| entry.0.due <= now) {
+            entries.extend(queue.entries.pop().map(|entry| entry.0));
+        }
+        drop(queue);
+        let mut delays = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let delay = Instant::now().saturating_duration_since(entry.due);
+            write(entry.message)?;
+            delays.push(delay);
+        }
+        queue = shared.queue();
+        for delay in delays {
+            queue.jitter.add(delay);
+        }
+    }
+}
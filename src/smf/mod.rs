@@ -0,0 +1,162 @@
+//! Reading and writing Standard Midi Files.
+//!
+//! `Smf::parse` decodes the bytes of a format 0, 1 or 2 file into tracks of
+//! `TrackEvent`s, channel messages are decoded into `ChannelMessage`s, which
+//! convert to `MidiMessage`s for an `OutputPort`.
+//!
+//! `SmfWriter` converts `MidiEvent`s with timestamps in milliseconds into an `Smf`,
+//! which `Smf::to_bytes` serializes.
+
+use std::fs;
+use std::path::Path;
+
+use message::ChannelMessage;
+use types::{Error, MidiMessage, Result};
+
+mod reader;
+mod tempo;
+mod writer;
+pub use self::tempo::*;
+pub use self::writer::SmfWriter;
+
+/// The layout of the tracks in a file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// Format 0, a single track with the events of all channels
+    SingleTrack,
+    /// Format 1, tracks that are played simultaneously
+    Parallel,
+    /// Format 2, independent single-track patterns
+    Sequential,
+}
+
+/// The unit of the delta times of a file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Timing {
+    /// Ticks per quarter note, the tempo is set by `MetaEvent::Tempo` events
+    Metrical(u16),
+    /// Ticks per frame of SMPTE time code with 24, 25, 29 (drop frame 30) or 30 frames per second
+    Timecode { fps: u8, ticks_per_frame: u8 },
+}
+
+/// A meta event, e.g. a tempo change or the name of a track.
+///
+/// Texts are decoded lossily, since files often use other encodings than UTF-8.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MetaEvent {
+    SequenceNumber(Option<u16>),
+    Text(String),
+    Copyright(String),
+    TrackName(String),
+    InstrumentName(String),
+    Lyric(String),
+    Marker(String),
+    CuePoint(String),
+    ChannelPrefix(u8),
+    Port(u8),
+    EndOfTrack,
+    /// Microseconds per quarter note
+    Tempo(u32),
+    SmpteOffset {
+        hours: u8,
+        minutes: u8,
+        seconds: u8,
+        frames: u8,
+        subframes: u8,
+    },
+    /// The `denominator` is a power of two, e.g. `3` for eighth notes
+    TimeSignature {
+        numerator: u8,
+        denominator: u8,
+        clocks_per_click: u8,
+        thirty_seconds_per_quarter: u8,
+    },
+    /// Positive numbers of sharps, negative numbers of flats
+    KeySignature {
+        sharps: i8,
+        minor: bool,
+    },
+    SequencerSpecific(Vec<u8>),
+    Unknown {
+        kind: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// The content of a `TrackEvent`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EventKind {
+    Midi(ChannelMessage),
+    /// A complete SysEx message including the leading `0xF0`, as expected by
+    /// `OutputPort::write_sysex` if it is not split into several events
+    SysEx(Vec<u8>),
+    /// Bytes to send as they are, e.g. the continuation of a split SysEx message
+    Escape(Vec<u8>),
+    Meta(MetaEvent),
+}
+impl EventKind {
+    /// Returns the `MidiMessage` of a channel message, `None` for other events.
+    pub fn midi_message(&self) -> Option<MidiMessage> {
+        match *self {
+            EventKind::Midi(msg) => Some(msg.into()),
+            _ => None,
+        }
+    }
+}
+
+/// An event of a track, with the ticks elapsed since the previous event of the track.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TrackEvent {
+    pub delta: u32,
+    pub kind: EventKind,
+}
+
+/// A track of a file.
+pub type Track = Vec<TrackEvent>;
+
+/// A Standard Midi File.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Smf {
+    pub format: Format,
+    pub timing: Timing,
+    pub tracks: Vec<Track>,
+}
+impl Smf {
+    /// Parses the content of a file.
+    ///
+    /// Unknown chunks are skipped. Returns an `Error::InvalidSmf(_)` if the data is malformed.
+    pub fn parse(data: &[u8]) -> Result<Smf> {
+        reader::parse(data)
+    }
+
+    /// Reads and parses the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Smf> {
+        let data = fs::read(path)?;
+        Smf::parse(&data)
+    }
+
+    /// Serializes the file.
+    ///
+    /// Returns an `Error::InvalidSmf(_)` if the file can't be represented, e.g. because
+    /// it has more than 65535 tracks or a resolution above 32767 ticks per quarter note.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        writer::write(self)
+    }
+
+    /// Writes the file to `path`, see `to_bytes`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_bytes()?).map_err(Error::from)
+    }
+}
+
+/// Returns the events of a track with the absolute time in ticks since its start.
+pub fn absolute_ticks(track: &[TrackEvent]) -> Vec<(u64, &EventKind)> {
+    let mut ticks = 0u64;
+    track
+        .iter()
+        .map(|event| {
+            ticks += u64::from(event.delta);
+            (ticks, &event.kind)
+        })
+        .collect()
+}
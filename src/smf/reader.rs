@@ -0,0 +1,232 @@
+use std::convert::TryFrom;
+
+use message::ChannelMessage;
+use smf::{EventKind, Format, MetaEvent, Smf, Timing, Track, TrackEvent};
+use types::{Error, MidiMessage, Result};
+
+/// Reads big-endian numbers and variable-length quantities from a chunk.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(Error::InvalidSmf("unexpected end of data"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(bytes.iter().fold(0, |acc, &b| acc << 8 | u32::from(b)))
+    }
+
+    /// Reads a variable-length quantity of at most four bytes.
+    fn vlq(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = value << 7 | u32::from(byte & 0x7F);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::InvalidSmf("variable-length quantity too long"))
+    }
+
+    /// Reads a length-prefixed block of bytes.
+    fn block(&mut self) -> Result<&'a [u8]> {
+        let len = self.vlq()? as usize;
+        self.bytes(len)
+    }
+
+    /// Reads a chunk and returns its type and content.
+    fn chunk(&mut self) -> Result<(&'a [u8], Reader<'a>)> {
+        let kind = self.bytes(4)?;
+        let len = self.u32()? as usize;
+        let data = self.bytes(len)?;
+        Ok((kind, Reader { data }))
+    }
+}
+
+pub fn parse(data: &[u8]) -> Result<Smf> {
+    let mut reader = Reader { data };
+    let (kind, mut header) = reader.chunk()?;
+    if kind != b"MThd" {
+        return Err(Error::InvalidSmf("missing MThd chunk"));
+    }
+    let format = match header.u16()? {
+        0 => Format::SingleTrack,
+        1 => Format::Parallel,
+        2 => Format::Sequential,
+        _ => return Err(Error::InvalidSmf("unknown format")),
+    };
+    let track_cnt = header.u16()?;
+    let division = header.u16()?;
+    let timing = if division & 0x8000 == 0 {
+        Timing::Metrical(division)
+    } else {
+        Timing::Timecode {
+            // the frame rate is stored as a negative number
+            fps: ((division >> 8) as u8 as i8).wrapping_neg() as u8,
+            ticks_per_frame: division as u8,
+        }
+    };
+
+    let mut tracks = Vec::with_capacity(track_cnt as usize);
+    while !reader.is_empty() && tracks.len() < track_cnt as usize {
+        let (kind, chunk) = reader.chunk()?;
+        if kind == b"MTrk" {
+            tracks.push(parse_track(chunk)?);
+        }
+    }
+    if tracks.len() < track_cnt as usize {
+        return Err(Error::InvalidSmf("missing MTrk chunk"));
+    }
+    Ok(Smf {
+        format,
+        timing,
+        tracks,
+    })
+}
+
+fn parse_track(mut reader: Reader) -> Result<Track> {
+    let mut track = Vec::new();
+    let mut running_status = None;
+    while !reader.is_empty() {
+        let delta = reader.vlq()?;
+        let first = reader.u8()?;
+        let kind = match first {
+            0xF0 => {
+                running_status = None;
+                let mut data = vec![0xF0];
+                data.extend_from_slice(reader.block()?);
+                EventKind::SysEx(data)
+            }
+            0xF7 => {
+                running_status = None;
+                EventKind::Escape(reader.block()?.to_vec())
+            }
+            0xFF => {
+                running_status = None;
+                let kind = reader.u8()?;
+                EventKind::Meta(parse_meta(kind, reader.block()?)?)
+            }
+            0xF1..=0xFE => return Err(Error::InvalidSmf("system message in track")),
+            _ => {
+                // without a status byte the previous status is repeated
+                let (status, data1) = if first & 0x80 != 0 {
+                    (first, reader.u8()?)
+                } else {
+                    let status =
+                        running_status.ok_or(Error::InvalidSmf("data byte without status"))?;
+                    (status, first)
+                };
+                running_status = Some(status);
+                let data2 = match status & 0xF0 {
+                    0xC0 | 0xD0 => 0,
+                    _ => reader.u8()?,
+                };
+                let msg = MidiMessage {
+                    status,
+                    data1,
+                    data2,
+                    data3: 0,
+                };
+                let msg = ChannelMessage::try_from(msg)
+                    .map_err(|_| Error::InvalidSmf("invalid channel message"))?;
+                EventKind::Midi(msg)
+            }
+        };
+        let end = kind == EventKind::Meta(MetaEvent::EndOfTrack);
+        track.push(TrackEvent { delta, kind });
+        if end {
+            break;
+        }
+    }
+    Ok(track)
+}
+
+fn parse_meta(kind: u8, data: &[u8]) -> Result<MetaEvent> {
+    let text = || String::from_utf8_lossy(data).into_owned();
+    let expect = |len: usize| {
+        if data.len() >= len {
+            Ok(())
+        } else {
+            Err(Error::InvalidSmf("meta event too short"))
+        }
+    };
+    Ok(match kind {
+        0x00 if data.is_empty() => MetaEvent::SequenceNumber(None),
+        0x00 => {
+            expect(2)?;
+            MetaEvent::SequenceNumber(Some(u16::from(data[0]) << 8 | u16::from(data[1])))
+        }
+        0x01 => MetaEvent::Text(text()),
+        0x02 => MetaEvent::Copyright(text()),
+        0x03 => MetaEvent::TrackName(text()),
+        0x04 => MetaEvent::InstrumentName(text()),
+        0x05 => MetaEvent::Lyric(text()),
+        0x06 => MetaEvent::Marker(text()),
+        0x07 => MetaEvent::CuePoint(text()),
+        0x20 => {
+            expect(1)?;
+            MetaEvent::ChannelPrefix(data[0])
+        }
+        0x21 => {
+            expect(1)?;
+            MetaEvent::Port(data[0])
+        }
+        0x2F => MetaEvent::EndOfTrack,
+        0x51 => {
+            expect(3)?;
+            MetaEvent::Tempo(data[..3].iter().fold(0, |acc, &b| acc << 8 | u32::from(b)))
+        }
+        0x54 => {
+            expect(5)?;
+            MetaEvent::SmpteOffset {
+                hours: data[0],
+                minutes: data[1],
+                seconds: data[2],
+                frames: data[3],
+                subframes: data[4],
+            }
+        }
+        0x58 => {
+            expect(4)?;
+            MetaEvent::TimeSignature {
+                numerator: data[0],
+                denominator: data[1],
+                clocks_per_click: data[2],
+                thirty_seconds_per_quarter: data[3],
+            }
+        }
+        0x59 => {
+            expect(2)?;
+            MetaEvent::KeySignature {
+                sharps: data[0] as i8,
+                minor: data[1] != 0,
+            }
+        }
+        0x7F => MetaEvent::SequencerSpecific(data.to_vec()),
+        kind => MetaEvent::Unknown {
+            kind,
+            data: data.to_vec(),
+        },
+    })
+}
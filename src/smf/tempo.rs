@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use smf::{absolute_ticks, EventKind, Format, MetaEvent, Smf, Timing};
+
+/// The tempo of a file without tempo events, 120 beats per minute.
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+/// A tempo change at a position in ticks.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TempoChange {
+    pub tick: u64,
+    /// Microseconds per quarter note
+    pub micros_per_quarter: u32,
+}
+
+/// Converts between ticks and time for a resolution in ticks per quarter note
+/// and a list of tempo changes.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TempoMap {
+    ppq: u16,
+    // sorted by tick, the first change is at tick 0
+    changes: Vec<TempoChange>,
+}
+impl TempoMap {
+    /// Creates a map with the given ticks per quarter note and the `DEFAULT_TEMPO`.
+    pub fn new(ppq: u16) -> Self {
+        TempoMap::with_tempo(ppq, DEFAULT_TEMPO)
+    }
+
+    /// Creates a map with the given ticks per quarter note and a constant tempo in
+    /// microseconds per quarter note.
+    pub fn with_tempo(ppq: u16, micros_per_quarter: u32) -> Self {
+        TempoMap {
+            ppq: ppq.max(1),
+            changes: vec![TempoChange {
+                tick: 0,
+                micros_per_quarter,
+            }],
+        }
+    }
+
+    /// Creates the map of a file from its `MetaEvent::Tempo` events.
+    ///
+    /// The tempo events of all tracks are used, except for format 2 files where
+    /// only the first track is used. For time code based files the map has a constant
+    /// tempo of one "quarter note" per second.
+    pub fn from_smf(smf: &Smf) -> Self {
+        let (ticks_per_frame, fps) = match smf.timing {
+            Timing::Metrical(ppq) => {
+                let tracks = match smf.format {
+                    Format::Sequential => &smf.tracks[..smf.tracks.len().min(1)],
+                    _ => &smf.tracks[..],
+                };
+                let mut map = TempoMap::new(ppq);
+                for track in tracks {
+                    for (tick, kind) in absolute_ticks(track) {
+                        if let EventKind::Meta(MetaEvent::Tempo(tempo)) = *kind {
+                            map.set_tempo(tick, tempo);
+                        }
+                    }
+                }
+                return map;
+            }
+            Timing::Timecode {
+                fps,
+                ticks_per_frame,
+            } => (u16::from(ticks_per_frame), u16::from(fps)),
+        };
+        if fps == 29 {
+            // drop frame time code runs at 30 / 1.001 frames per second
+            TempoMap::with_tempo(ticks_per_frame * 30, 1_001_000)
+        } else {
+            TempoMap::with_tempo(ticks_per_frame * fps, 1_000_000)
+        }
+    }
+
+    /// Returns the resolution in ticks per quarter note.
+    pub fn ppq(&self) -> u16 {
+        self.ppq
+    }
+
+    /// Returns the tempo changes, sorted by tick and starting at tick `0`.
+    pub fn changes(&self) -> &[TempoChange] {
+        &self.changes
+    }
+
+    /// Sets the tempo in microseconds per quarter note from `tick` on,
+    /// replacing a change at the same tick.
+    pub fn set_tempo(&mut self, tick: u64, micros_per_quarter: u32) {
+        let change = TempoChange {
+            tick,
+            micros_per_quarter,
+        };
+        match self.changes.binary_search_by_key(&tick, |c| c.tick) {
+            Ok(pos) => self.changes[pos] = change,
+            Err(pos) => self.changes.insert(pos, change),
+        }
+    }
+
+    /// Returns the tempo in microseconds per quarter note at `tick`.
+    pub fn tempo_at(&self, tick: u64) -> u32 {
+        self.segment_at_tick(tick).1.micros_per_quarter
+    }
+
+    /// Returns the time in microseconds from tick `0` to `tick`.
+    pub fn tick_to_micros(&self, tick: u64) -> u64 {
+        let (start, change) = self.segment_at_tick(tick);
+        start + self.ticks_to_micros(tick - change.tick, change.micros_per_quarter)
+    }
+
+    /// Returns the tick at the time in microseconds since tick `0`, rounded to the nearest tick.
+    pub fn micros_to_tick(&self, micros: u64) -> u64 {
+        let mut start = 0;
+        let mut segment = self.changes[0];
+        for &change in &self.changes[1..] {
+            let end = start
+                + self.ticks_to_micros(change.tick - segment.tick, segment.micros_per_quarter);
+            if end > micros {
+                break;
+            }
+            start = end;
+            segment = change;
+        }
+        let tempo = u64::from(segment.micros_per_quarter.max(1));
+        segment.tick + ((micros - start) * u64::from(self.ppq) + tempo / 2) / tempo
+    }
+
+    /// Returns the time from tick `0` to `tick`.
+    pub fn to_duration(&self, tick: u64) -> Duration {
+        Duration::from_micros(self.tick_to_micros(tick))
+    }
+
+    /// Returns the tick at the given time since tick `0`.
+    pub fn to_tick(&self, duration: Duration) -> u64 {
+        self.micros_to_tick(duration.as_micros() as u64)
+    }
+
+    fn ticks_to_micros(&self, ticks: u64, micros_per_quarter: u32) -> u64 {
+        ticks * u64::from(micros_per_quarter) / u64::from(self.ppq)
+    }
+
+    /// Returns the start in microseconds and the tempo of the segment containing `tick`.
+    fn segment_at_tick(&self, tick: u64) -> (u64, TempoChange) {
+        let mut start = 0;
+        let mut segment = self.changes[0];
+        for &change in &self.changes[1..] {
+            if change.tick > tick {
+                break;
+            }
+            start += self.ticks_to_micros(change.tick - segment.tick, segment.micros_per_quarter);
+            segment = change;
+        }
+        (start, segment)
+    }
+}
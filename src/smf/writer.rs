@@ -0,0 +1,360 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use message::{self, ChannelMessage, SystemCommon, SystemRealtime};
+use smf::{EventKind, Format, MetaEvent, Smf, TempoMap, Timing, Track, TrackEvent};
+use types::{Error, MidiEvent, MidiMessage, Result};
+
+/// The largest delta time a variable-length quantity can hold.
+const MAX_DELTA: u32 = 0x0FFF_FFFF;
+
+/// Collects events with timestamps in milliseconds and converts them to an `Smf`
+/// using a `TempoMap`.
+///
+/// Timestamps are milliseconds since the start of the file, subtract the timestamp of
+/// the first recorded event from PortMidi timestamps first.
+#[derive(Clone, Debug)]
+pub struct SmfWriter {
+    tempo_map: TempoMap,
+    // meta events of the conductor track, e.g. the time signature
+    meta: Vec<(u64, MetaEvent)>,
+    tracks: Vec<Vec<(u64, EventKind)>>,
+}
+impl SmfWriter {
+    /// Creates a writer without tracks, the tempo changes of `tempo_map` are written
+    /// as tempo events.
+    pub fn new(tempo_map: TempoMap) -> Self {
+        SmfWriter {
+            tempo_map,
+            meta: Vec::new(),
+            tracks: Vec::new(),
+        }
+    }
+
+    /// Returns the tempo map used to convert milliseconds to ticks.
+    pub fn tempo_map(&self) -> &TempoMap {
+        &self.tempo_map
+    }
+
+    /// Adds a track and returns its index, `name` is written as a `MetaEvent::TrackName`.
+    pub fn add_track(&mut self, name: Option<&str>) -> usize {
+        let events = name
+            .map(|name| vec![(0, EventKind::Meta(MetaEvent::TrackName(name.to_owned())))])
+            .unwrap_or_default();
+        self.tracks.push(events);
+        self.tracks.len() - 1
+    }
+
+    /// Sets the time signature at the start of the file, `denominator` is a power of two,
+    /// e.g. `2` for quarter notes.
+    pub fn time_signature(&mut self, numerator: u8, denominator: u8) {
+        self.add_meta(
+            0,
+            MetaEvent::TimeSignature {
+                numerator,
+                denominator,
+                clocks_per_click: 24,
+                thirty_seconds_per_quarter: 8,
+            },
+        );
+    }
+
+    /// Adds a meta event to the conductor track, the first track of a format 1 file.
+    pub fn add_meta(&mut self, timestamp: u32, event: MetaEvent) {
+        let tick = self.tick(timestamp);
+        self.meta.push((tick, event));
+    }
+
+    /// Adds an event to the given track.
+    ///
+    /// Returns an `Error::InvalidSmf(_)` if there is no such track.
+    pub fn add_event(&mut self, track: usize, timestamp: u32, event: EventKind) -> Result<()> {
+        let tick = self.tick(timestamp);
+        self.tracks
+            .get_mut(track)
+            .ok_or(Error::InvalidSmf("no such track"))?
+            .push((tick, event));
+        Ok(())
+    }
+
+    /// Adds a SysEx message including the leading `0xF0` and trailing EOX to the given track.
+    pub fn add_sysex(&mut self, track: usize, timestamp: u32, data: &[u8]) -> Result<()> {
+        if data.first() != Some(&0xF0) {
+            return Err(Error::InvalidMessage);
+        }
+        self.add_event(track, timestamp, EventKind::SysEx(data.to_vec()))
+    }
+
+    /// Adds `MidiEvent`s to the given track.
+    ///
+    /// System common and realtime messages are written as escaped bytes, parts of
+    /// SysEx messages result in an `Error::InvalidMessage`, use `add_sysex` for those.
+    pub fn add_midi_events(&mut self, track: usize, events: &[MidiEvent]) -> Result<()> {
+        for event in events {
+            let kind = if let Ok(msg) = ChannelMessage::try_from(event.message) {
+                EventKind::Midi(msg)
+            } else if SystemCommon::try_from(event.message).is_ok()
+                || SystemRealtime::try_from(event.message).is_ok()
+            {
+                EventKind::Escape(message::message_bytes(event.message))
+            } else {
+                return Err(Error::InvalidMessage);
+            };
+            self.add_event(track, event.timestamp, kind)?;
+        }
+        Ok(())
+    }
+
+    /// Builds a format 0 or format 1 file.
+    ///
+    /// A format 0 file has one track with all events, a format 1 file has a conductor
+    /// track with the tempo and meta events followed by the added tracks.
+    /// Returns an `Error::InvalidSmf(_)` for `Format::Sequential`.
+    pub fn to_smf(&self, format: Format) -> Result<Smf> {
+        let mut conductor = self
+            .tempo_map
+            .changes()
+            .iter()
+            .map(|c| {
+                (
+                    c.tick,
+                    EventKind::Meta(MetaEvent::Tempo(c.micros_per_quarter)),
+                )
+            })
+            .chain(
+                self.meta
+                    .iter()
+                    .map(|&(tick, ref meta)| (tick, EventKind::Meta(meta.clone()))),
+            )
+            .collect::<Vec<_>>();
+        let tracks = match format {
+            Format::SingleTrack => {
+                for track in &self.tracks {
+                    conductor.extend(track.iter().cloned());
+                }
+                vec![to_track(conductor)]
+            }
+            Format::Parallel => Some(to_track(conductor))
+                .into_iter()
+                .chain(self.tracks.iter().cloned().map(to_track))
+                .collect(),
+            Format::Sequential => {
+                return Err(Error::InvalidSmf("format 2 files can not be written"));
+            }
+        };
+        Ok(Smf {
+            format,
+            timing: Timing::Metrical(self.tempo_map.ppq()),
+            tracks,
+        })
+    }
+
+    fn tick(&self, timestamp: u32) -> u64 {
+        self.tempo_map
+            .to_tick(Duration::from_millis(u64::from(timestamp)))
+    }
+}
+
+/// Sorts events by tick, converts them to delta times and terminates the track.
+///
+/// Gaps longer than `MAX_DELTA` are split by empty text events.
+fn to_track(mut events: Vec<(u64, EventKind)>) -> Track {
+    events.retain(|(_, kind)| *kind != EventKind::Meta(MetaEvent::EndOfTrack));
+    events.sort_by_key(|&(tick, _)| tick);
+    let end = events.last().map_or(0, |&(tick, _)| tick);
+    events.push((end, EventKind::Meta(MetaEvent::EndOfTrack)));
+    let mut track = Vec::with_capacity(events.len());
+    let mut last = 0;
+    for (tick, kind) in events {
+        let mut delta = tick - last;
+        last = tick;
+        while delta > u64::from(MAX_DELTA) {
+            track.push(TrackEvent {
+                delta: MAX_DELTA,
+                kind: EventKind::Meta(MetaEvent::Text(String::new())),
+            });
+            delta -= u64::from(MAX_DELTA);
+        }
+        track.push(TrackEvent {
+            delta: delta as u32,
+            kind,
+        });
+    }
+    track
+}
+
+/// Serializes a file, channel messages use running status.
+///
+/// Delta times longer than `MAX_DELTA` are split by empty text events, values that
+/// don't fit in their fields result in an `Error::InvalidSmf(_)`.
+pub fn write(smf: &Smf) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"MThd");
+    push_u32(&mut data, 6);
+    push_u16(
+        &mut data,
+        match smf.format {
+            Format::SingleTrack => 0,
+            Format::Parallel => 1,
+            Format::Sequential => 2,
+        },
+    );
+    let track_count =
+        u16::try_from(smf.tracks.len()).map_err(|_| Error::InvalidSmf("too many tracks"))?;
+    push_u16(&mut data, track_count);
+    push_u16(
+        &mut data,
+        match smf.timing {
+            Timing::Metrical(ppq) if ppq > 0x7FFF => {
+                return Err(Error::InvalidSmf("too many ticks per quarter note"));
+            }
+            Timing::Metrical(ppq) => ppq,
+            Timing::Timecode {
+                fps,
+                ticks_per_frame,
+            } => u16::from((fps as i8).wrapping_neg() as u8) << 8 | u16::from(ticks_per_frame),
+        },
+    );
+    for track in &smf.tracks {
+        let chunk = write_track(track)?;
+        let len = u32::try_from(chunk.len()).map_err(|_| Error::InvalidSmf("track too long"))?;
+        data.extend_from_slice(b"MTrk");
+        push_u32(&mut data, len);
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+fn write_track(track: &[TrackEvent]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut running_status = None;
+    for event in track {
+        let mut delta = event.delta;
+        while delta > MAX_DELTA {
+            push_vlq(&mut data, MAX_DELTA)?;
+            data.extend_from_slice(&[0xFF, 0x01, 0x00]);
+            running_status = None;
+            delta -= MAX_DELTA;
+        }
+        push_vlq(&mut data, delta)?;
+        match event.kind {
+            EventKind::Midi(msg) => {
+                let msg = MidiMessage::from(msg);
+                if running_status != Some(msg.status) {
+                    data.push(msg.status);
+                    running_status = Some(msg.status);
+                }
+                data.push(msg.data1);
+                if !matches!(msg.status & 0xF0, 0xC0 | 0xD0) {
+                    data.push(msg.data2);
+                }
+            }
+            EventKind::SysEx(ref sysex) => {
+                running_status = None;
+                data.push(0xF0);
+                let body = sysex.get(1..).unwrap_or(&[]);
+                push_len(&mut data, body.len())?;
+                data.extend_from_slice(body);
+            }
+            EventKind::Escape(ref bytes) => {
+                running_status = None;
+                data.push(0xF7);
+                push_len(&mut data, bytes.len())?;
+                data.extend_from_slice(bytes);
+            }
+            EventKind::Meta(ref meta) => {
+                running_status = None;
+                let (kind, body) = meta_bytes(meta);
+                data.push(0xFF);
+                data.push(kind);
+                push_len(&mut data, body.len())?;
+                data.extend_from_slice(&body);
+            }
+        }
+    }
+    Ok(data)
+}
+
+fn meta_bytes(meta: &MetaEvent) -> (u8, Vec<u8>) {
+    let text = |kind, text: &String| (kind, text.as_bytes().to_vec());
+    match *meta {
+        MetaEvent::SequenceNumber(None) => (0x00, Vec::new()),
+        MetaEvent::SequenceNumber(Some(number)) => (0x00, vec![(number >> 8) as u8, number as u8]),
+        MetaEvent::Text(ref t) => text(0x01, t),
+        MetaEvent::Copyright(ref t) => text(0x02, t),
+        MetaEvent::TrackName(ref t) => text(0x03, t),
+        MetaEvent::InstrumentName(ref t) => text(0x04, t),
+        MetaEvent::Lyric(ref t) => text(0x05, t),
+        MetaEvent::Marker(ref t) => text(0x06, t),
+        MetaEvent::CuePoint(ref t) => text(0x07, t),
+        MetaEvent::ChannelPrefix(channel) => (0x20, vec![channel]),
+        MetaEvent::Port(port) => (0x21, vec![port]),
+        MetaEvent::EndOfTrack => (0x2F, Vec::new()),
+        MetaEvent::Tempo(tempo) => (
+            0x51,
+            vec![(tempo >> 16) as u8, (tempo >> 8) as u8, tempo as u8],
+        ),
+        MetaEvent::SmpteOffset {
+            hours,
+            minutes,
+            seconds,
+            frames,
+            subframes,
+        } => (0x54, vec![hours, minutes, seconds, frames, subframes]),
+        MetaEvent::TimeSignature {
+            numerator,
+            denominator,
+            clocks_per_click,
+            thirty_seconds_per_quarter,
+        } => (
+            0x58,
+            vec![
+                numerator,
+                denominator,
+                clocks_per_click,
+                thirty_seconds_per_quarter,
+            ],
+        ),
+        MetaEvent::KeySignature { sharps, minor } => (0x59, vec![sharps as u8, minor as u8]),
+        MetaEvent::SequencerSpecific(ref data) => (0x7F, data.clone()),
+        MetaEvent::Unknown { kind, ref data } => (kind, data.clone()),
+    }
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&[(value >> 8) as u8, value as u8]);
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&[
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ]);
+}
+
+/// Writes the length of an event body as a variable-length quantity.
+fn push_len(data: &mut Vec<u8>, len: usize) -> Result<()> {
+    match u32::try_from(len) {
+        Ok(len) if len <= MAX_DELTA => push_vlq(data, len),
+        _ => Err(Error::InvalidSmf("event too long")),
+    }
+}
+
+/// Writes a variable-length quantity, values are limited to `MAX_DELTA`.
+fn push_vlq(data: &mut Vec<u8>, value: u32) -> Result<()> {
+    if value > MAX_DELTA {
+        return Err(Error::InvalidSmf("variable-length quantity too large"));
+    }
+    let mut shift = 21;
+    while shift > 0 && value >> shift == 0 {
+        shift -= 7;
+    }
+    while shift > 0 {
+        data.push((value >> shift) as u8 & 0x7F | 0x80);
+        shift -= 7;
+    }
+    data.push(value as u8 & 0x7F);
+    Ok(())
+}
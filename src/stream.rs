@@ -0,0 +1,158 @@
+use std::cmp;
+use std::pin::Pin;
+use std::sync::{Condvar, Mutex, Once};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use io::{Backoff, InputPort, OutputPort, MAX_BACKOFF_US};
+use types::{Error, MidiEvent, Result};
+
+static POLLER_STARTED: Once = Once::new();
+static TIMERS: Mutex<Vec<(Instant, Waker)>> = Mutex::new(Vec::new());
+static TIMERS_CHANGED: Condvar = Condvar::new();
+
+/// Registers a task to be woken by the background poller at `deadline`.
+///
+/// The poller thread never calls into PortMidi itself, it only wakes the waiting
+/// tasks when their deadline has passed and sleeps until the earliest one otherwise.
+/// Reading is always done by the task that owns the port.
+fn wake_at(deadline: Instant, waker: &Waker) {
+    POLLER_STARTED.call_once(|| {
+        thread::spawn(|| {
+            let mut timers = TIMERS.lock().unwrap();
+            loop {
+                let now = Instant::now();
+                let mut due = Vec::new();
+                let mut i = 0;
+                while i < timers.len() {
+                    if timers[i].0 <= now {
+                        due.push(timers.swap_remove(i).1);
+                    } else {
+                        i += 1;
+                    }
+                }
+                if !due.is_empty() {
+                    drop(timers);
+                    for waker in due {
+                        waker.wake();
+                    }
+                    timers = TIMERS.lock().unwrap();
+                    continue;
+                }
+                timers = match timers.iter().map(|&(deadline, _)| deadline).min() {
+                    Some(deadline) => {
+                        TIMERS_CHANGED
+                            .wait_timeout(timers, deadline - now)
+                            .unwrap()
+                            .0
+                    }
+                    None => TIMERS_CHANGED.wait(timers).unwrap(),
+                };
+            }
+        });
+    });
+    let mut timers = TIMERS.lock().unwrap();
+    match timers.iter_mut().find(|(_, w)| w.will_wake(waker)) {
+        Some(timer) => timer.0 = cmp::min(timer.0, deadline),
+        None => timers.push((deadline, waker.clone())),
+    }
+    TIMERS_CHANGED.notify_one();
+}
+
+/// An `InputPort` as a `futures_core::Stream` of `MidiEvent`s, created by `InputPort::into_stream`.
+///
+/// While no events are available the stream is polled again with the same increasing
+/// interval as `InputPort::read_timeout`, which is reset when an event arrives.
+/// The stream does not end, errors are returned as items.
+pub struct EventStream<'a> {
+    port: InputPort<'a>,
+    backoff: Backoff,
+    max_poll_interval: Duration,
+}
+impl<'a> EventStream<'a> {
+    /// Sets the maximum interval the port is polled with while no events arrive,
+    /// 1 ms by default.
+    ///
+    /// A longer interval reduces the wake-ups of idle streams at the cost of latency.
+    pub fn set_max_poll_interval(&mut self, interval: Duration) {
+        self.max_poll_interval = interval;
+        self.backoff = Backoff::with_max(interval);
+    }
+
+    /// Returns the underlying `InputPort`.
+    pub fn into_inner(self) -> InputPort<'a> {
+        self.port
+    }
+}
+impl<'a> Stream for EventStream<'a> {
+    type Item = Result<MidiEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        match this.port.read() {
+            Ok(Some(event)) => {
+                this.backoff = Backoff::with_max(this.max_poll_interval);
+                Poll::Ready(Some(Ok(event)))
+            }
+            Ok(None) => {
+                wake_at(Instant::now() + this.backoff.next_delay(), cx.waker());
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Some(Err(err))),
+        }
+    }
+}
+
+/// An `OutputPort` as a `futures_sink::Sink` of `MidiEvent`s, created by `OutputPort::into_sink`.
+///
+/// PortMidi buffers written events itself, so the sink is always ready and
+/// every event is written as soon as it is sent.
+pub struct EventSink<'a> {
+    port: OutputPort<'a>,
+}
+impl<'a> EventSink<'a> {
+    /// Returns the underlying `OutputPort`.
+    pub fn into_inner(self) -> OutputPort<'a> {
+        self.port
+    }
+}
+impl<'a> Sink<MidiEvent> for EventSink<'a> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, event: MidiEvent) -> Result<()> {
+        self.port.write_event(event)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'a> InputPort<'a> {
+    /// Converts the port into an `EventStream`.
+    pub fn into_stream(self) -> EventStream<'a> {
+        EventStream {
+            port: self,
+            backoff: Backoff::new(),
+            max_poll_interval: Duration::from_micros(MAX_BACKOFF_US),
+        }
+    }
+}
+impl<'a> OutputPort<'a> {
+    /// Converts the port into an `EventSink`.
+    pub fn into_sink(self) -> EventSink<'a> {
+        EventSink { port: self }
+    }
+}
@@ -0,0 +1,165 @@
+use std::mem;
+
+use ffi;
+use message::SystemRealtime;
+use types::{Error, MidiEvent, MidiMessage, Result};
+
+const SYSEX: u8 = 0xF0;
+
+/// A complete SysEx message.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SysExEvent {
+    /// The message bytes including the leading `0xF0` and the trailing `0xF7`.
+    pub data: Vec<u8>,
+    /// The timestamp of the first part of the message.
+    pub timestamp: ffi::PmTimestamp,
+}
+
+/// An input event as returned by the `SysExAssembler`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InputEvent {
+    /// Any message that is not part of a SysEx message, including realtime
+    /// messages that were interleaved with SysEx data.
+    Midi(MidiEvent),
+    SysEx(SysExEvent),
+}
+
+enum State {
+    Idle,
+    Receiving(SysExEvent),
+    /// Skips the rest of a SysEx message that exceeded the maximum length.
+    Discarding,
+}
+
+/// Reassembles SysEx messages from the `MidiEvent`s returned by `InputPort::read_n`.
+///
+/// PortMidi packs up to four SysEx bytes into a single `MidiEvent`, realtime messages
+/// can be interleaved with these events. The assembler collects the SysEx bytes until
+/// the terminating `0xF7` and passes everything else through unchanged.
+///
+/// A SysEx message that is interrupted by a non-realtime status byte is reported as
+/// `Error::SysExAborted`, one that exceeds the maximum length as `Error::SysExTruncated`.
+pub struct SysExAssembler {
+    state: State,
+    max_len: usize,
+}
+impl SysExAssembler {
+    /// Creates an assembler without a limit for the SysEx message length.
+    pub fn new() -> Self {
+        SysExAssembler::with_max_len(usize::MAX)
+    }
+
+    /// Creates an assembler that drops SysEx messages longer than `max_len` bytes.
+    pub fn with_max_len(max_len: usize) -> Self {
+        SysExAssembler {
+            state: State::Idle,
+            max_len,
+        }
+    }
+
+    /// Returns `true` if a SysEx message has been started but not yet terminated.
+    pub fn is_receiving(&self) -> bool {
+        !matches!(self.state, State::Idle)
+    }
+
+    /// Processes all given events and returns the reassembled input events in order.
+    pub fn feed<I: IntoIterator<Item = MidiEvent>>(
+        &mut self,
+        events: I,
+    ) -> Vec<Result<InputEvent>> {
+        let mut out = Vec::new();
+        for event in events {
+            self.process(event, &mut out);
+        }
+        out
+    }
+
+    /// Processes a single event, see `feed`.
+    pub fn push(&mut self, event: MidiEvent) -> Vec<Result<InputEvent>> {
+        let mut out = Vec::new();
+        self.process(event, &mut out);
+        out
+    }
+
+    /// Resets the assembler, e.g. after the input port has been closed.
+    /// Returns an `Error::SysExTruncated` if a SysEx message was still being received.
+    pub fn finish(&mut self) -> Result<()> {
+        match mem::replace(&mut self.state, State::Idle) {
+            State::Receiving(_) => Err(Error::SysExTruncated),
+            _ => Ok(()),
+        }
+    }
+
+    fn process(&mut self, event: MidiEvent, out: &mut Vec<Result<InputEvent>>) {
+        let status = event.message.status;
+        if self.is_receiving() {
+            if SystemRealtime::from_status(status).is_some() {
+                out.push(Ok(InputEvent::Midi(event)));
+                return;
+            }
+            if status >= 0x80 && status != ffi::MIDI_EOX {
+                if let State::Receiving(_) = mem::replace(&mut self.state, State::Idle) {
+                    out.push(Err(Error::SysExAborted));
+                }
+            }
+        }
+        if !self.is_receiving() {
+            if status != SYSEX {
+                out.push(Ok(InputEvent::Midi(event)));
+                return;
+            }
+            self.state = State::Receiving(SysExEvent {
+                data: Vec::new(),
+                timestamp: event.timestamp,
+            });
+        }
+
+        let MidiMessage {
+            status,
+            data1,
+            data2,
+            data3,
+        } = event.message;
+        for &byte in &[status, data1, data2, data3] {
+            if let Some(realtime) = SystemRealtime::from_status(byte) {
+                out.push(Ok(InputEvent::Midi(MidiEvent {
+                    message: MidiMessage::from(realtime),
+                    timestamp: event.timestamp,
+                })));
+                continue;
+            }
+            match mem::replace(&mut self.state, State::Idle) {
+                State::Receiving(mut sysex) => {
+                    let is_start = byte == SYSEX && sysex.data.is_empty();
+                    if byte >= 0x80 && byte != ffi::MIDI_EOX && !is_start {
+                        // a status byte in the middle of the data terminates the message
+                        out.push(Err(Error::SysExAborted));
+                        return;
+                    }
+                    sysex.data.push(byte);
+                    if byte == ffi::MIDI_EOX {
+                        out.push(Ok(InputEvent::SysEx(sysex)));
+                        return;
+                    } else if sysex.data.len() >= self.max_len {
+                        out.push(Err(Error::SysExTruncated));
+                        self.state = State::Discarding;
+                    } else {
+                        self.state = State::Receiving(sysex);
+                    }
+                }
+                State::Discarding => {
+                    if byte >= 0x80 {
+                        return;
+                    }
+                    self.state = State::Discarding;
+                }
+                State::Idle => return,
+            }
+        }
+    }
+}
+impl Default for SysExAssembler {
+    fn default() -> Self {
+        SysExAssembler::new()
+    }
+}
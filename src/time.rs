@@ -0,0 +1,135 @@
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ffi;
+use types::{Error, Result};
+
+/// The PortTime millisecond clock.
+///
+/// This is the timebase of `MidiEvent::timestamp` for ports that were opened
+/// without a custom `TimeSource`, timestamps are milliseconds since the clock was started.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    origin: Instant,
+    origin_timestamp: ffi::PmTimestamp,
+}
+impl Clock {
+    /// Starts the PortTime clock with a resolution of 1 ms, unless it is already running.
+    /// Returns an `Error::PortTime(_)` if the clock could not be started.
+    pub fn new() -> Result<Self> {
+        if unsafe { ffi::Pt_Started() } == 0 {
+            match unsafe { ffi::Pt_Start(1, None, ptr::null_mut()) } {
+                ffi::PtError::PtNoError | ffi::PtError::PtAlreadyStarted => {}
+                err => return Err(Error::PortTime(err)),
+            }
+        }
+        Ok(Clock {
+            origin: Instant::now(),
+            origin_timestamp: unsafe { ffi::Pt_Time() },
+        })
+    }
+
+    /// Returns the current time in milliseconds.
+    pub fn now(&self) -> ffi::PmTimestamp {
+        unsafe { ffi::Pt_Time() }
+    }
+
+    /// Converts a timestamp to the `Duration` since the clock was started.
+    pub fn to_duration(timestamp: ffi::PmTimestamp) -> Duration {
+        Duration::from_millis(u64::from(timestamp))
+    }
+
+    /// Converts a `Duration` since the clock was started to a timestamp,
+    /// saturating at `PmTimestamp::MAX`.
+    pub fn from_duration(duration: Duration) -> ffi::PmTimestamp {
+        let millis = duration.as_millis();
+        if millis > u128::from(ffi::PmTimestamp::MAX) {
+            ffi::PmTimestamp::MAX
+        } else {
+            millis as ffi::PmTimestamp
+        }
+    }
+
+    /// Returns the `Instant` corresponding to the given timestamp.
+    ///
+    /// Timestamps before the earliest `Instant` the platform can represent are mapped to
+    /// an earlier `Instant` that can be represented, they are in the past either way.
+    pub fn to_instant(&self, timestamp: ffi::PmTimestamp) -> Instant {
+        if timestamp >= self.origin_timestamp {
+            self.origin + Clock::to_duration(timestamp - self.origin_timestamp)
+        } else {
+            let mut offset = Clock::to_duration(self.origin_timestamp - timestamp);
+            loop {
+                match self.origin.checked_sub(offset) {
+                    Some(instant) => return instant,
+                    None => offset /= 2,
+                }
+            }
+        }
+    }
+
+    /// Returns the timestamp corresponding to the given `Instant`, e.g. to schedule
+    /// an event on an `OutputPort` that was opened with a latency.
+    pub fn from_instant(&self, instant: Instant) -> ffi::PmTimestamp {
+        if instant >= self.origin {
+            let offset = Clock::from_duration(instant - self.origin);
+            self.origin_timestamp.saturating_add(offset)
+        } else {
+            let offset = Clock::from_duration(self.origin - instant);
+            self.origin_timestamp.saturating_sub(offset)
+        }
+    }
+}
+impl TimeSource for Clock {
+    fn now(&self) -> ffi::PmTimestamp {
+        Clock::now(self)
+    }
+}
+
+/// A clock that PortMidi uses to timestamp input events and to schedule output events,
+/// instead of the default PortTime millisecond clock.
+///
+/// Closures of the type `Fn() -> PmTimestamp` implement this trait, so e.g. an audio
+/// engine's sample clock can be shared by wrapping it in a closure.
+/// The source is called from PortMidi's internal threads and must not panic.
+pub trait TimeSource: Send + Sync {
+    /// Returns the current time in milliseconds.
+    fn now(&self) -> ffi::PmTimestamp;
+}
+impl<F> TimeSource for F
+where
+    F: Fn() -> ffi::PmTimestamp + Send + Sync,
+{
+    fn now(&self) -> ffi::PmTimestamp {
+        self()
+    }
+}
+
+/// Keeps a `TimeSource` alive at a stable address while a stream uses it.
+pub(crate) struct TimeProc {
+    source: Box<Arc<dyn TimeSource>>,
+}
+impl TimeProc {
+    pub(crate) fn new(source: Arc<dyn TimeSource>) -> Self {
+        TimeProc {
+            source: Box::new(source),
+        }
+    }
+
+    /// Returns the procedure to pass to `Pm_OpenInput` or `Pm_OpenOutput`.
+    pub(crate) fn time_proc(&self) -> ffi::PmTimeProcPtr {
+        Some(time_proc)
+    }
+
+    /// Returns the `time_info` pointer to pass along with `time_proc`.
+    pub(crate) fn time_info(&self) -> *const c_void {
+        &*self.source as *const Arc<dyn TimeSource> as *const c_void
+    }
+}
+
+extern "C" fn time_proc(time_info: *mut c_void) -> ffi::PmTimestamp {
+    let source = unsafe { &*(time_info as *const Arc<dyn TimeSource>) };
+    source.now()
+}
@@ -1,9 +1,13 @@
 use std::convert::{From, Into};
 use std::error;
 use std::fmt;
+use std::io;
+use std::ops::Deref;
 use std::os::raw::c_int;
 use std::result;
+use std::sync::{Arc, RwLock};
 
+use device::DeviceInfo;
 use ffi;
 
 pub type PortMidiDeviceId = c_int;
@@ -14,32 +18,128 @@ impl From<ffi::PmError> for Result<()> {
     fn from(err: ffi::PmError) -> Self {
         match err {
             ffi::PmError::PmNoError | ffi::PmError::PmGotData => Ok(()),
-            _ => Err(Error::PortMidi(err)),
+            _ => Err(Error::from(err)),
         }
     }
 }
 
-/// PortMidi error type.
+/// The operation on a device that failed, see `Error::Device`.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Open,
+    Read,
+    Poll,
+    Write,
+    Abort,
+    Close,
+}
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Operation::Open => "open",
+            Operation::Read => "read from",
+            Operation::Poll => "poll",
+            Operation::Write => "write to",
+            Operation::Abort => "abort output to",
+            Operation::Close => "close",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// PortMidi error type.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
     PortMidi(ffi::PmError),
+    /// A host error of the underlying Midi API, with the text reported when it occurred
+    HostError(String),
+    PortTime(ffi::PtError),
     Unknown,
     Unimplemented,
     NoDefaultDevice,
     NotAnInputDevice,
     NotAnOutputDevice,
-    Invalid,
+    DeviceInUse,
+    LibraryInUse,
+    NoMatchingDevice,
+    /// A device written to through a `DeviceWatcher` is not connected
+    Disconnected,
+    /// More than one device matched a query, with the matching devices
+    AmbiguousDevice(Vec<DeviceInfo>),
+    InvalidMessage,
+    SysExAborted,
+    SysExTruncated,
+    /// A SysEx message to write does not end with EOX
+    MissingEox,
+    /// An output latency in milliseconds that PortMidi cannot represent, i.e. above `i32::MAX`
+    InvalidLatency(u32),
+    /// The backend reported a negative number of devices
+    InvalidDeviceCount(i32),
+    /// A Standard Midi File is malformed, with the reason
+    InvalidSmf(&'static str),
+    /// `Player::play` was called while the player is playing
+    AlreadyPlaying,
+    /// Reading or writing a file failed
+    Io(IoError),
+    /// An operation on a port failed, `source` is the error reported by the backend
+    Device {
+        operation: Operation,
+        id: PortMidiDeviceId,
+        name: String,
+        source: Box<Error>,
+    },
+}
+impl Error {
+    /// Returns the innermost error, e.g. the `source` of an `Error::Device`.
+    pub fn root_cause(&self) -> &Error {
+        match *self {
+            Error::Device { ref source, .. } => source.root_cause(),
+            ref err => err,
+        }
+    }
+
+    /// Wraps the error with the failed operation and the device it failed on.
+    pub(crate) fn on_device(self, operation: Operation, device: &DeviceInfo) -> Error {
+        Error::Device {
+            operation,
+            id: device.id(),
+            name: device.name().clone(),
+            source: Box::new(self),
+        }
+    }
 }
 impl From<ffi::PmError> for Error {
+    /// Captures the host error text for a `PmHostError`, it is lost after the next error.
     fn from(err: ffi::PmError) -> Self {
-        Error::PortMidi(err)
+        match err {
+            ffi::PmError::PmHostError => Error::HostError(ffi::host_error_text()),
+            err => Error::PortMidi(err),
+        }
     }
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::PortMidi(pm_err) => write!(f, "{}", pm_err),
-            err => write!(f, "{:?}", err),
+            Error::HostError(ref text) => write!(f, "PortMidi: Host error: {}", text),
+            Error::PortTime(pt_err) => write!(f, "PortTime: {}", pt_err),
+            Error::Device {
+                operation,
+                id,
+                ref name,
+                ref source,
+            } => write!(f, "Could not {} device {} ({}): {}", operation, id, name, source),
+            Error::AmbiguousDevice(ref devices) => {
+                write!(f, "More than one matching device:")?;
+                for (i, device) in devices.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{} ({})", sep, device.id(), device.name())?;
+                }
+                Ok(())
+            }
+            Error::InvalidSmf(reason) => write!(f, "Invalid Standard Midi File: {}", reason),
+            Error::Io(ref err) => write!(f, "I/O error: {}", **err),
+            ref err => write!(f, "{:?}", err),
         }
     }
 }
@@ -59,15 +159,97 @@ impl error::Error for Error {
                 ffi::PmError::PmInternalError => "PortMidi: `Internal PortMidi Error'",
                 ffi::PmError::PmBufferMaxSize => "PortMidi: `Buffer cannot be made larger'",
             },
+            Error::HostError(_) => "PortMidi: `Host error'",
+            Error::PortTime(pt_error) => match pt_error {
+                ffi::PtError::PtNoError => "",
+                ffi::PtError::PtHostError => "PortTime: `Host error'",
+                ffi::PtError::PtAlreadyStarted => "PortTime: `Timer already started'",
+                ffi::PtError::PtAlreadyStopped => "PortTime: `Timer already stopped'",
+                ffi::PtError::PtInsufficientMemory => "PortTime: `Insufficient memory'",
+            },
             Error::Unknown => "portmidi-rs: Unknown",
             Error::Unimplemented => "portmidi-rs: Unimplemented",
             Error::NoDefaultDevice => "portmidi-rs: No default device",
             Error::NotAnInputDevice => "portmidi-rs: Not an input device",
             Error::NotAnOutputDevice => "portmidi-rs: Not an output device",
-            Error::Invalid => "portmidi-rs: Invalid",
+            Error::DeviceInUse => "portmidi-rs: Device is already opened",
+            Error::LibraryInUse => "portmidi-rs: PortMidi is used by another context",
+            Error::NoMatchingDevice => "portmidi-rs: No matching device",
+            Error::Disconnected => "portmidi-rs: Device is disconnected",
+            Error::AmbiguousDevice(_) => "portmidi-rs: More than one matching device",
+            Error::InvalidMessage => "portmidi-rs: Invalid Midi message",
+            Error::SysExAborted => "portmidi-rs: SysEx message aborted by a status byte",
+            Error::SysExTruncated => "portmidi-rs: SysEx message truncated",
+            Error::MissingEox => "portmidi-rs: SysEx message does not end with EOX",
+            Error::InvalidLatency(_) => "portmidi-rs: Invalid output latency",
+            Error::InvalidDeviceCount(_) => "portmidi-rs: Invalid device count",
+            Error::InvalidSmf(_) => "portmidi-rs: Invalid Standard Midi File",
+            Error::AlreadyPlaying => "portmidi-rs: Player is already playing",
+            Error::Io(_) => "portmidi-rs: I/O error",
+            Error::Device { .. } => "portmidi-rs: Device operation failed",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Device { ref source, .. } => Some(&**source),
+            Error::Io(ref err) => Some(&**err),
+            _ => None,
         }
     }
 }
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(IoError(Arc::new(err)))
+    }
+}
+
+/// An `io::Error` that can be cloned, see `Error::Io`.
+///
+/// Two `IoError`s are equal if they have the same `io::ErrorKind`.
+#[derive(Clone, Debug)]
+pub struct IoError(Arc<io::Error>);
+impl Deref for IoError {
+    type Target = io::Error;
+
+    fn deref(&self) -> &io::Error {
+        &self.0
+    }
+}
+impl PartialEq for IoError {
+    fn eq(&self, other: &IoError) -> bool {
+        self.kind() == other.kind()
+    }
+}
+impl Eq for IoError {}
+
+type DropErrorHook = Arc<dyn Fn(&Error) + Send + Sync>;
+
+static DROP_ERROR_HOOK: RwLock<Option<DropErrorHook>> = RwLock::new(None);
+
+/// Sets a function that is called with the errors that occur while ports and contexts
+/// are dropped, e.g. to log them.
+///
+/// Without a hook these errors are ignored, use `InputPort::close`, `OutputPort::close`
+/// and `PortMidi::terminate` to handle them.
+pub fn set_drop_error_hook<F: Fn(&Error) + Send + Sync + 'static>(hook: F) {
+    *DROP_ERROR_HOOK.write().unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(hook));
+}
+
+/// Removes the function set by `set_drop_error_hook`.
+pub fn clear_drop_error_hook() {
+    *DROP_ERROR_HOOK.write().unwrap_or_else(|err| err.into_inner()) = None;
+}
+
+/// Passes an error that occurred in a `Drop` implementation to the hook, if there is one.
+///
+/// The hook is called without holding the lock, so it may drop ports or set another hook.
+pub(crate) fn report_drop_error(err: &Error) {
+    let hook = DROP_ERROR_HOOK.read().unwrap_or_else(|err| err.into_inner()).clone();
+    if let Some(hook) = hook {
+        hook(err);
+    }
+}
 
 /// Represents a Midi message.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -0,0 +1,301 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use context::PortMidi;
+use device::DeviceInfo;
+use io::{Backoff, InputPort, OutputPort};
+use query::DeviceQuery;
+use types::{Error, MidiEvent, Result};
+
+/// A change of the available devices, reported by a `DeviceWatcher`.
+#[derive(Clone, Debug)]
+pub enum DeviceEvent {
+    /// A device was connected, with its new id
+    DeviceAdded(DeviceInfo),
+    /// A device was disconnected, with its old id
+    DeviceRemoved(DeviceInfo),
+    /// Re-enumerating the devices failed, the watcher tries again after the next interval
+    ScanFailed(Error),
+}
+
+struct InputBinding {
+    name: String,
+    buffer_size: usize,
+    callback: Box<dyn FnMut(MidiEvent) + Send>,
+}
+
+struct OutputBinding {
+    name: String,
+    buffer_size: usize,
+    events: Receiver<MidiEvent>,
+    connected: Arc<AtomicBool>,
+}
+
+/// Writes to an output device that is reopened by a `DeviceWatcher` whenever it is
+/// connected, created by `DeviceWatcherBuilder::reopen_output`.
+#[derive(Clone)]
+pub struct WatchedOutput {
+    name: String,
+    events: Sender<MidiEvent>,
+    connected: Arc<AtomicBool>,
+}
+impl WatchedOutput {
+    /// Returns the name of the watched device.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns `true` if the device is currently connected.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+
+    /// Queues a `MidiEvent` to be written by the watcher thread.
+    ///
+    /// Returns an `Error::Disconnected` if the device is not connected or
+    /// the watcher has stopped.
+    pub fn write_event<T: Into<MidiEvent>>(&self, event: T) -> Result<()> {
+        if !self.is_connected() {
+            return Err(Error::Disconnected);
+        }
+        self.events
+            .send(event.into())
+            .map_err(|_| Error::Disconnected)
+    }
+}
+
+/// Configures a `DeviceWatcher`, created by `DeviceWatcher::builder`.
+pub struct DeviceWatcherBuilder {
+    context: PortMidi,
+    interval: Duration,
+    inputs: Vec<InputBinding>,
+    outputs: Vec<OutputBinding>,
+}
+impl DeviceWatcherBuilder {
+    /// Opens the input device with the given name whenever it is connected and
+    /// calls `callback` for every event that is read from it.
+    pub fn reopen_input<F>(&mut self, name: &str, buffer_size: usize, callback: F)
+    where
+        F: FnMut(MidiEvent) + Send + 'static,
+    {
+        self.inputs.push(InputBinding {
+            name: name.to_owned(),
+            buffer_size,
+            callback: Box::new(callback),
+        });
+    }
+
+    /// Opens the output device with the given name whenever it is connected and
+    /// returns a `WatchedOutput` to write to it.
+    pub fn reopen_output(&mut self, name: &str, buffer_size: usize) -> WatchedOutput {
+        let (tx, rx) = mpsc::channel();
+        let connected = Arc::new(AtomicBool::new(false));
+        self.outputs.push(OutputBinding {
+            name: name.to_owned(),
+            buffer_size,
+            events: rx,
+            connected: connected.clone(),
+        });
+        WatchedOutput {
+            name: name.to_owned(),
+            events: tx,
+            connected,
+        }
+    }
+
+    /// Starts the watcher thread, `callback` is called for every added or removed device
+    /// and for failed scans.
+    pub fn start<F>(self, mut callback: F) -> DeviceWatcher
+    where
+        F: FnMut(DeviceEvent) + Send + 'static,
+    {
+        let DeviceWatcherBuilder {
+            mut context,
+            interval,
+            mut inputs,
+            outputs,
+        } = self;
+        // the outputs are usable right away, not only after the thread opened them
+        for output in &outputs {
+            let connected = find_device(&context, &output.name, false).is_some();
+            output.connected.store(connected, Ordering::Release);
+        }
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = thread::spawn({
+            let running = running.clone();
+            move || {
+                while running.load(Ordering::Acquire) {
+                    serve(&context, interval, &running, &mut inputs, &outputs);
+                    // no scan and no more callbacks once the watcher is stopped
+                    if !running.load(Ordering::Acquire) {
+                        break;
+                    }
+                    match context.rescan() {
+                        Ok(changes) => {
+                            for device in changes.removed {
+                                callback(DeviceEvent::DeviceRemoved(device));
+                            }
+                            for device in changes.added {
+                                callback(DeviceEvent::DeviceAdded(device));
+                            }
+                        }
+                        Err(err) => callback(DeviceEvent::ScanFailed(err)),
+                    }
+                }
+                context
+            }
+        });
+        DeviceWatcher {
+            running,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Handle of a background thread that periodically re-enumerates the devices of a
+/// `PortMidi` context and reports added and removed devices.
+///
+/// PortMidi can only re-enumerate devices by re-initializing, so the watcher owns the
+/// context. Ports that should survive a device being disconnected and connected again
+/// are opened by the watcher itself, see `DeviceWatcherBuilder::reopen_input` and
+/// `DeviceWatcherBuilder::reopen_output`.
+///
+/// Re-initializing invalidates all streams, so these ports are closed while the devices
+/// are scanned and reopened right after. Pending input is read and queued output is
+/// written before, output queued during the scan is written once the port is reopened.
+/// Only the output queued for a device that was disconnected is discarded.
+///
+/// The thread is stopped and joined when the handle is dropped.
+pub struct DeviceWatcher {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<PortMidi>>,
+}
+impl DeviceWatcher {
+    /// Starts a watcher that scans for devices every `interval` and calls
+    /// `callback` for every added or removed device.
+    pub fn new<F>(context: PortMidi, interval: Duration, callback: F) -> DeviceWatcher
+    where
+        F: FnMut(DeviceEvent) + Send + 'static,
+    {
+        DeviceWatcher::builder(context, interval).start(callback)
+    }
+
+    /// Returns a builder to configure the devices that are reopened by the watcher.
+    pub fn builder(context: PortMidi, interval: Duration) -> DeviceWatcherBuilder {
+        DeviceWatcherBuilder {
+            context,
+            interval,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Returns `false` if the watcher thread has stopped, e.g. because a callback panicked.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+            && self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    /// Stops the watcher thread, waits for it to finish and returns the context.
+    /// Returns an `Error::Unknown` if the thread panicked.
+    pub fn stop(mut self) -> Result<PortMidi> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<PortMidi> {
+        self.running.store(false, Ordering::Release);
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(|_| Error::Unknown),
+            None => Err(Error::Unknown),
+        }
+    }
+}
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+fn find_device(context: &PortMidi, name: &str, input: bool) -> Option<DeviceInfo> {
+    let query = DeviceQuery::new().name(name);
+    let query = if input { query.input() } else { query.output() };
+    query.find_all(context).ok()?.into_iter().next()
+}
+
+/// Opens the connected devices of the bindings and passes events from and to them
+/// until the next scan is due, the pending events are passed on before the ports are closed.
+fn serve(
+    context: &PortMidi,
+    interval: Duration,
+    running: &AtomicBool,
+    inputs: &mut [InputBinding],
+    outputs: &[OutputBinding],
+) {
+    let deadline = Instant::now() + interval;
+    let mut input_ports = inputs
+        .iter()
+        .map(|input| {
+            find_device(context, &input.name, true)
+                .and_then(|device| InputPort::new(context, device, input.buffer_size).ok())
+        })
+        .collect::<Vec<_>>();
+    let mut output_ports = outputs
+        .iter()
+        .map(|output| {
+            let port = find_device(context, &output.name, false)
+                .and_then(|device| OutputPort::new(context, device, output.buffer_size).ok());
+            output.connected.store(port.is_some(), Ordering::Release);
+            port
+        })
+        .collect::<Vec<_>>();
+
+    let mut backoff = Backoff::new();
+    while running.load(Ordering::Acquire) && Instant::now() < deadline {
+        if transfer(inputs, &mut input_ports, outputs, &mut output_ports) {
+            backoff.wait();
+        } else {
+            backoff = Backoff::new();
+        }
+    }
+    // a busy device must not postpone the scan, pass on what is pending once more
+    transfer(inputs, &mut input_ports, outputs, &mut output_ports);
+}
+
+/// Passes the available events from and to the ports once, returns `true` if there were none.
+fn transfer(
+    inputs: &mut [InputBinding],
+    input_ports: &mut [Option<InputPort<'_>>],
+    outputs: &[OutputBinding],
+    output_ports: &mut [Option<OutputPort<'_>>],
+) -> bool {
+    let mut idle = true;
+    for (input, port) in inputs.iter_mut().zip(input_ports) {
+        let events = match port.as_ref().map(|port| port.read_n(input.buffer_size)) {
+            Some(Ok(Some(events))) => events,
+            // the device was probably disconnected, it is reopened after the next scan
+            Some(Err(_)) => {
+                *port = None;
+                continue;
+            }
+            _ => continue,
+        };
+        idle = false;
+        for event in events {
+            (input.callback)(event);
+        }
+    }
+    for (output, port) in outputs.iter().zip(output_ports) {
+        // events for a disconnected device are discarded
+        while let Ok(event) = output.events.try_recv() {
+            idle = false;
+            if let Some(Err(_)) = port.as_mut().map(|port| port.write_event(event)) {
+                *port = None;
+                output.connected.store(false, Ordering::Release);
+            }
+        }
+    }
+    idle
+}
@@ -1,3 +1,7 @@
+#[cfg(feature = "async")]
+extern crate futures_core;
+#[cfg(feature = "async")]
+extern crate futures_sink;
 extern crate portmidi;
 extern crate rci;
 
@@ -100,6 +104,21 @@ fn test_threads() {
     }
 }
 
+#[test]
+fn test_shared() {
+    // needs access to the Midi system, see `test_main`
+    if rci::Ci::new().is_none() {
+        let first = portmidi::PortMidi::shared().unwrap();
+        let second = portmidi::PortMidi::shared().unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        // the library stays initialized for the shared context
+        let other = portmidi::PortMidi::new().unwrap();
+        assert_eq!(other.device_count(), first.device_count());
+        drop(other);
+        assert!(first.devices().is_ok());
+    }
+}
+
 #[test]
 fn test_types() {
     let message = portmidi::MidiMessage::from(0x007F3C81);
@@ -110,3 +129,1009 @@ fn test_types() {
     // Velocity: 127
     assert_eq!(message.data2, 127);
 }
+
+#[test]
+fn test_channel_message() {
+    use portmidi::{Channel, ChannelMessage, MidiMessage, U14, U7};
+    use std::convert::TryFrom;
+
+    let note_on = ChannelMessage::try_from(MidiMessage::from(0x007F3C91)).unwrap();
+    assert_eq!(note_on,
+               ChannelMessage::NoteOn {
+                   channel: Channel::new(1).unwrap(),
+                   note: U7::new(60).unwrap(),
+                   velocity: U7::MAX,
+               });
+    assert_eq!(MidiMessage::from(note_on), MidiMessage::from(0x007F3C91));
+
+    let bend = ChannelMessage::PitchBend {
+        channel: Channel::new(15).unwrap(),
+        value: U14::new(0x1234).unwrap(),
+    };
+    let raw = MidiMessage::from(bend);
+    assert_eq!((raw.status, raw.data1, raw.data2), (0xEF, 0x34, 0x24));
+    assert_eq!(ChannelMessage::try_from(raw).unwrap(), bend);
+
+    // system messages and data bytes with the msb set are rejected
+    assert!(ChannelMessage::try_from(MidiMessage::from(0x000000F8)).is_err());
+    assert!(ChannelMessage::try_from(MidiMessage::from(0x00803C90)).is_err());
+    assert!(Channel::new(16).is_none());
+}
+
+#[test]
+fn test_system_message() {
+    use portmidi::{Message, MidiMessage, SystemCommon, SystemRealtime, U14};
+    use std::convert::TryFrom;
+
+    assert_eq!(Message::try_from(MidiMessage::from(0xF8)).unwrap(),
+               Message::Realtime(SystemRealtime::TimingClock));
+    assert_eq!(SystemRealtime::try_from(MidiMessage::from(0xFC)).unwrap(),
+               SystemRealtime::Stop);
+    assert!(SystemRealtime::try_from(MidiMessage::from(0x007F3C90)).is_err());
+
+    let position = SystemCommon::SongPosition(U14::new(1000).unwrap());
+    let raw = MidiMessage::from(position);
+    assert_eq!((raw.status, raw.data1, raw.data2), (0xF2, 0x68, 0x07));
+    assert_eq!(Message::try_from(raw).unwrap(), Message::Common(position));
+
+    let frame = SystemCommon::MtcQuarterFrame {
+        message_type: 3,
+        value: 9,
+    };
+    assert_eq!(MidiMessage::from(frame).data1, 0x39);
+    assert_eq!(SystemCommon::try_from(MidiMessage::from(frame)).unwrap(), frame);
+
+    // SysEx and undefined status bytes can't be decoded
+    assert!(Message::try_from(MidiMessage::from(0xF0)).is_err());
+    assert!(Message::try_from(MidiMessage::from(0xFD)).is_err());
+}
+
+#[test]
+fn test_sysex_assembler() {
+    use portmidi::{Error, InputEvent, MidiEvent, MidiMessage, SysExAssembler};
+
+    fn event(raw: u32, timestamp: u32) -> MidiEvent {
+        MidiEvent {
+            message: MidiMessage::from(raw),
+            timestamp,
+        }
+    }
+
+    let mut assembler = SysExAssembler::new();
+    let out = assembler.feed(vec![event(0x030201F0, 10),
+                                  event(0x000000F8, 11),
+                                  event(0x00F70504, 12),
+                                  event(0x007F3C90, 13)]);
+    assert_eq!(out.len(), 3);
+    assert_eq!(out[0], Ok(InputEvent::Midi(event(0xF8, 11))));
+    match out[1] {
+        Ok(InputEvent::SysEx(ref sysex)) => {
+            assert_eq!(sysex.data, vec![0xF0, 1, 2, 3, 4, 5, 0xF7]);
+            assert_eq!(sysex.timestamp, 10);
+        }
+        ref other => panic!("expected sysex, got {:?}", other),
+    }
+    assert_eq!(out[2], Ok(InputEvent::Midi(event(0x007F3C90, 13))));
+    assert!(!assembler.is_receiving());
+
+    // a note on in the middle of a sysex aborts it but is still delivered
+    let out = assembler.feed(vec![event(0x030201F0, 0), event(0x007F3C90, 1)]);
+    assert_eq!(out, vec![Err(Error::SysExAborted),
+                         Ok(InputEvent::Midi(event(0x007F3C90, 1)))]);
+
+    let mut assembler = SysExAssembler::with_max_len(4);
+    let out = assembler.feed(vec![event(0x030201F0, 0), event(0x00F70504, 1)]);
+    assert_eq!(out, vec![Err(Error::SysExTruncated)]);
+    assert!(!assembler.is_receiving());
+
+    let mut assembler = SysExAssembler::new();
+    assert!(assembler.push(event(0x030201F0, 0)).is_empty());
+    assert_eq!(assembler.finish(), Err(Error::SysExTruncated));
+}
+
+#[test]
+fn test_clock() {
+    use std::time::Duration;
+
+    let clock = portmidi::Clock::new().unwrap();
+    let before = clock.now();
+    let after = clock.now();
+    assert!(after >= before);
+    assert_eq!(portmidi::Clock::to_duration(1500), Duration::from_millis(1500));
+    let instant = clock.to_instant(before + 250);
+    assert_eq!(clock.from_instant(instant), before + 250);
+}
+
+#[test]
+fn test_time_source() {
+    use portmidi::{MidiEvent, MidiMessage, MockBackend, PortMidi, TimeSource};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("mock in");
+    let output = backend.add_output("mock out");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let time = Arc::new(AtomicU32::new(1000));
+    let now = {
+        let time = time.clone();
+        move || time.load(Ordering::SeqCst)
+    };
+    let source: Arc<dyn TimeSource> = Arc::new(now);
+
+    // input events are stamped with the time of arrival
+    let in_port = context
+        .input_port_with_time_source(context.device(input).unwrap(), 16, source.clone())
+        .unwrap();
+    backend.push_input(input, MidiEvent { message: MidiMessage::from(0x007F3C90), timestamp: 5 })
+        .unwrap();
+    time.store(1500, Ordering::SeqCst);
+    backend.push_input(input, MidiMessage::from(0x00003C80)).unwrap();
+    let events = in_port.read_n(16).unwrap().unwrap();
+    assert_eq!(events.iter().map(|event| event.timestamp).collect::<Vec<_>>(), vec![1000, 1500]);
+
+    // with a latency, events without a timestamp are scheduled at the source's time
+    let mut out_port = context
+        .output_port_with_time_source(context.device(output).unwrap(), 16, 10, source)
+        .unwrap();
+    time.store(2000, Ordering::SeqCst);
+    out_port.write_message(MidiMessage::from(0x007F3C90)).unwrap();
+    out_port.write_event(MidiEvent { message: MidiMessage::from(0x00003C80), timestamp: 2500 })
+        .unwrap();
+    let events = backend.take_output(output).unwrap();
+    assert_eq!(events.iter().map(|event| event.timestamp).collect::<Vec<_>>(), vec![2000, 2500]);
+}
+
+#[test]
+fn test_mock_backend() {
+    use portmidi::{InputEvent, MidiEvent, MidiMessage, MockBackend, PortMidi, SysExAssembler};
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("mock in");
+    let output = backend.add_output("mock out");
+    backend.connect(output, input).unwrap();
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    assert_eq!(context.device_count(), 2);
+    assert_eq!(context.default_input_device_id(), Ok(input));
+    assert_eq!(context.device(output).unwrap().name(), "mock out");
+    assert!(context.device(2).is_err());
+
+    let mut in_port = context.default_input_port(16).unwrap();
+    let mut out_port = context.default_output_port(16).unwrap();
+    assert!(backend.is_open(input));
+    assert!(context.device(input).unwrap().is_opened());
+    assert_eq!(context.input_port(context.device(input).unwrap(), 16).err(),
+               Some(portmidi::Error::DeviceInUse));
+
+    assert_eq!(in_port.poll(), Ok(false));
+    backend.push_input(input, MidiMessage::from(0x007F3C90)).unwrap();
+    assert_eq!(in_port.read(), Ok(Some(MidiEvent::from(MidiMessage::from(0x007F3C90)))));
+    assert_eq!(in_port.read(), Ok(None));
+
+    // written events are recorded and looped back to the input
+    out_port.write_message(MidiMessage::from(0x00003C80)).unwrap();
+    out_port.write_sysex(5, &[0xF0, 1, 2, 3, 4, 0xF7]).unwrap();
+    assert_eq!(backend.take_output(output).unwrap().len(), 3);
+    assert!(backend.take_output(output).unwrap().is_empty());
+    let events = in_port.read_n(16).unwrap().unwrap();
+    let out = SysExAssembler::new().feed(events);
+    assert_eq!(out[0], Ok(InputEvent::Midi(MidiEvent::from(MidiMessage::from(0x00003C80)))));
+    match out[1] {
+        Ok(InputEvent::SysEx(ref sysex)) => assert_eq!(sysex.data, vec![0xF0, 1, 2, 3, 4, 0xF7]),
+        ref other => panic!("expected sysex, got {:?}", other),
+    }
+
+    drop(in_port);
+    assert!(!backend.is_open(input));
+}
+
+#[test]
+fn test_device_info() {
+    use portmidi::{BackendDevice, Direction, Error, MockBackend, PortMidi};
+
+    let backend = MockBackend::new();
+    let duplex = backend.add_device(BackendDevice {
+        name: "duplex".to_owned(),
+        interface: "ALSA".to_owned(),
+        input: true,
+        output: true,
+        opened: true,
+    });
+    let output = backend.add_output("out");
+    let context = PortMidi::with_backend(backend).unwrap();
+
+    let info = context.device(duplex).unwrap();
+    assert_eq!(info.interface(), "ALSA");
+    assert_eq!(info.direction(), Direction::Duplex);
+    assert!(info.is_input() && info.is_output());
+    assert!(!info.is_opened());
+    assert_eq!(context.device(output).unwrap().direction(), Direction::Output);
+    assert_eq!(context.input_port(context.device(output).unwrap(), 16).err(),
+               Some(Error::NotAnInputDevice));
+
+    let _port = context.input_port(info, 16).unwrap();
+    assert!(context.device(duplex).unwrap().is_opened());
+}
+
+#[test]
+fn test_owned_ports() {
+    use portmidi::{MidiMessage, MockBackend, OwnedOutputPort, PortMidi};
+
+    struct App {
+        output: OwnedOutputPort,
+    }
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("in");
+    let output = backend.add_output("out");
+    backend.connect(output, input).unwrap();
+    let context = Arc::new(PortMidi::with_backend(backend).unwrap());
+
+    let mut app = App {
+        output: context.owned_output_port(context.device(output).unwrap(), 16).unwrap(),
+    };
+    let mut in_port = context.owned_input_port(context.device(input).unwrap(), 16).unwrap();
+    drop(context);
+
+    let reader = thread::spawn(move || in_port.read_blocking().unwrap());
+    app.output.write_message(MidiMessage::from([0x90, 60, 100, 0])).unwrap();
+    assert_eq!(reader.join().unwrap().message.data1, 60);
+}
+
+#[test]
+fn test_rescan() {
+    use portmidi::{MockBackend, PortMidi};
+
+    let backend = MockBackend::new();
+    let first = backend.add_input("first");
+    backend.add_output("second");
+    let mut context = PortMidi::with_backend(backend.clone()).unwrap();
+    assert!(context.rescan().unwrap().is_empty());
+
+    backend.remove_device(first).unwrap();
+    backend.add_input("third");
+    let changes = context.rescan().unwrap();
+    assert_eq!(context.device_count(), 2);
+    assert_eq!(changes.removed.len(), 1);
+    assert_eq!(changes.removed[0].name(), "first");
+    assert_eq!(changes.added.len(), 1);
+    assert_eq!(changes.added[0].name(), "third");
+    assert_eq!(changes.added[0].id(), 1);
+}
+
+#[test]
+fn test_device_query() {
+    use portmidi::{BackendDevice, DeviceQuery, Error, MockBackend, PortMidi};
+
+    let backend = MockBackend::new();
+    backend.add_input("nanoKONTROL2 MIDI 1");
+    let nano_out = backend.add_output("nanoKONTROL2 MIDI 1");
+    backend.add_input("Launchpad Mini");
+    backend.add_input("Launchpad Mini 2");
+    backend.add_device(BackendDevice {
+        name: "IAC Bus 1".to_owned(),
+        interface: "CoreMIDI".to_owned(),
+        input: true,
+        output: false,
+        opened: false,
+    });
+    let context = PortMidi::with_backend(backend).unwrap();
+
+    assert_eq!(context.find_output("nanokontrol").unwrap().id(), nano_out);
+    // an exact match wins over substring matches
+    assert_eq!(context.find_input("Launchpad Mini").unwrap().name(), "Launchpad Mini");
+    match context.find_input("launchpad") {
+        Err(ref err @ Error::AmbiguousDevice(_)) => assert_eq!(
+            err.to_string(),
+            "More than one matching device: 2 (Launchpad Mini), 3 (Launchpad Mini 2)"
+        ),
+        other => panic!("expected an ambiguous device, got {:?}", other),
+    }
+    assert_eq!(context.find_output("Launchpad").err(), Some(Error::NoMatchingDevice));
+
+    let query = DeviceQuery::new().name_matches("^launchpad mini ?2?$").ignore_case();
+    assert_eq!(query.find_all(&context).unwrap().len(), 2);
+    let query = DeviceQuery::new().name_matches("MIDI \\d").input();
+    assert!(query.find(&context).is_err());
+    let query = DeviceQuery::new().name_matches("K.*L2 MIDI").input();
+    assert_eq!(query.find(&context).unwrap().name(), "nanoKONTROL2 MIDI 1");
+    let query = DeviceQuery::new().interface("coremidi");
+    assert_eq!(query.find(&context).unwrap().name(), "IAC Bus 1");
+}
+
+#[test]
+fn test_device_watcher() {
+    use portmidi::{DeviceEvent, DeviceWatcher, Error, MidiMessage, MockBackend, PortMidi};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let backend = MockBackend::new();
+    backend.add_input("keys");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let mut builder = DeviceWatcher::builder(context, Duration::from_millis(5));
+    let synth = builder.reopen_output("synth", 16);
+    let (keys_tx, keys_rx) = mpsc::channel();
+    builder.reopen_input("keys", 16, move |event| keys_tx.send(event).unwrap());
+    let watcher = builder.start(move |event| tx.send(event).unwrap());
+    assert!(!synth.is_connected());
+    assert_eq!(synth.write_event(MidiMessage::from([0x90, 60, 100, 0])), Err(Error::Disconnected));
+
+    let id = backend.add_output("synth");
+    match rx.recv_timeout(Duration::from_secs(1)).unwrap() {
+        DeviceEvent::DeviceAdded(device) => assert_eq!(device.name(), "synth"),
+        event => panic!("unexpected {:?}", event),
+    }
+    synth.write_event(MidiMessage::from([0x90, 60, 100, 0])).unwrap();
+    let mut written = Vec::new();
+    for _ in 0..100 {
+        written.extend(backend.take_output(id).unwrap());
+        if !written.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(written.len(), 1);
+
+    // input keeps arriving across scans
+    for note in 60..66 {
+        backend.push_input(0, MidiMessage::from([0x90, note, 100, 0])).unwrap();
+        thread::sleep(Duration::from_millis(2));
+    }
+    for note in 60..66 {
+        assert_eq!(keys_rx.recv_timeout(Duration::from_secs(1)).unwrap().message.data1, note);
+    }
+
+    backend.remove_device(0).unwrap();
+    match rx.recv_timeout(Duration::from_secs(1)).unwrap() {
+        DeviceEvent::DeviceRemoved(device) => assert_eq!(device.name(), "keys"),
+        event => panic!("unexpected {:?}", event),
+    }
+    let context = watcher.stop().unwrap();
+    assert_eq!(context.device_count(), 1);
+}
+
+#[test]
+fn test_device_error() {
+    use portmidi::{Error, MockBackend, Operation, PmError, PortMidi};
+    use std::error::Error as StdError;
+
+    let backend = MockBackend::new();
+    let output = backend.add_output("out");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let port = context.output_port(context.device(output).unwrap(), 16).unwrap();
+    assert_eq!(port.write_sysex(0, &[0xf0, 0x7d]), Err(Error::MissingEox));
+
+    backend.remove_device(output).unwrap();
+    let err = port.write_sysex(0, &[0xf0, 0x7d, 0xf7]).unwrap_err();
+    match err {
+        Error::Device { operation, id, ref name, .. } => {
+            assert_eq!((operation, id, name.as_str()), (Operation::Write, output, "out"));
+        }
+        ref err => panic!("unexpected {:?}", err),
+    }
+    assert_eq!(err.root_cause(), &Error::PortMidi(PmError::PmBadPtr));
+    assert!(err.source().is_some());
+}
+
+#[test]
+fn test_close_errors() {
+    use portmidi::{Error, MockBackend, Operation, PortMidi};
+    use std::sync::Mutex;
+
+    let backend = MockBackend::new();
+    let first = backend.add_input("close first");
+    backend.add_input("close second");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let first_port = context.input_port(context.device(first).unwrap(), 16).unwrap();
+    let second_port = context.input_port(context.device(first + 1).unwrap(), 16).unwrap();
+
+    // the hook is global, only collect the errors of this test
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    portmidi::set_drop_error_hook({
+        let errors = errors.clone();
+        move |err: &Error| match *err {
+            Error::Device { ref name, .. } if name.starts_with("close ") => {
+                errors.lock().unwrap().push(err.clone());
+                // the hook may replace itself without deadlocking
+                portmidi::clear_drop_error_hook();
+            }
+            _ => (),
+        }
+    });
+
+    backend.remove_device(first).unwrap();
+    drop(first_port);
+    assert!(second_port.close().is_ok());
+    portmidi::clear_drop_error_hook();
+
+    let errors = errors.lock().unwrap();
+    assert_eq!(errors.len(), 1);
+    match errors[0] {
+        Error::Device { operation, ref name, .. } => {
+            assert_eq!((operation, name.as_str()), (Operation::Close, "close first"));
+        }
+        ref err => panic!("unexpected {:?}", err),
+    }
+    assert!(context.terminate().is_ok());
+}
+
+#[test]
+fn test_abort() {
+    use portmidi::{MockBackend, PortMidi};
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("in");
+    let output = backend.add_output("out");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let in_port = context.input_port(context.device(input).unwrap(), 16).unwrap();
+    let too_late = context.output_port_with_latency(context.device(output).unwrap(), 16, u32::MAX);
+    assert_eq!(too_late.err(), Some(portmidi::Error::InvalidLatency(u32::MAX)));
+    assert!(!backend.is_open(output));
+    let out_port = context.output_port_with_latency(context.device(output).unwrap(), 16, 10).unwrap();
+    assert!(!in_port.has_host_error() && !out_port.has_host_error());
+
+    backend.set_host_error(input, true).unwrap();
+    assert!(in_port.has_host_error());
+    out_port.abort().unwrap();
+    assert!(!backend.is_open(output));
+}
+
+#[test]
+fn test_smf_reader() {
+    use portmidi::smf::{self, EventKind, Format, MetaEvent, Smf, Timing};
+    use portmidi::{ChannelMessage, Error, MidiMessage};
+
+    let data = [
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 2, 0x01, 0xE0, // format 1, 2 tracks, 480 ppq
+        b'M', b'T', b'r', b'k', 0, 0, 0, 19,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // tempo 500000
+        0x00, 0xFF, 0x58, 0x04, 0x04, 0x02, 0x18, 0x08, // 4/4
+        0x00, 0xFF, 0x2F, 0x00,
+        b'X', b'x', b'x', b'x', 0, 0, 0, 1, 0, // unknown chunk
+        b'M', b'T', b'r', b'k', 0, 0, 0, 27,
+        0x00, 0xFF, 0x03, 0x02, b'P', b'f',
+        0x00, 0x90, 0x3C, 0x64,
+        0x83, 0x60, 0x3C, 0x00, // running status, delta 480
+        0x00, 0xF0, 0x03, 0x7D, 0x01, 0xF7,
+        0x00, 0xC1, 0x05,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let file = Smf::parse(&data).unwrap();
+    assert_eq!(file.format, Format::Parallel);
+    assert_eq!(file.timing, Timing::Metrical(480));
+    assert_eq!(file.tracks.len(), 2);
+    assert_eq!(file.tracks[0][0].kind, EventKind::Meta(MetaEvent::Tempo(500_000)));
+
+    let track = smf::absolute_ticks(&file.tracks[1]);
+    assert_eq!(track.len(), 6);
+    assert_eq!(track[0].1, &EventKind::Meta(MetaEvent::TrackName("Pf".to_owned())));
+    assert_eq!(track[1].1.midi_message(), Some(MidiMessage::from([0x90, 0x3C, 0x64, 0])));
+    assert_eq!(track[2].0, 480);
+    assert_eq!(track[2].1.midi_message(), Some(MidiMessage::from([0x90, 0x3C, 0x00, 0])));
+    assert_eq!(track[3].1, &EventKind::SysEx(vec![0xF0, 0x7D, 0x01, 0xF7]));
+    match *track[4].1 {
+        EventKind::Midi(ChannelMessage::ProgramChange { channel, program }) => {
+            assert_eq!((channel.index(), program.value()), (1, 5))
+        }
+        ref event => panic!("unexpected {:?}", event),
+    }
+
+    assert_eq!(Smf::parse(&data[..30]), Err(Error::InvalidSmf("unexpected end of data")));
+
+    // the io::Error is kept as the source
+    let err = Smf::open("/nonexistent/file.mid").unwrap_err();
+    match err {
+        Error::Io(ref io_err) => assert_eq!(io_err.kind(), std::io::ErrorKind::NotFound),
+        ref err => panic!("unexpected {:?}", err),
+    }
+    let source = std::error::Error::source(&err).unwrap();
+    let io_err = source.downcast_ref::<std::io::Error>().unwrap();
+    assert_eq!(io_err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_smf_writer() {
+    use portmidi::smf::{self, EventKind, Format, MetaEvent, Smf, SmfWriter, TempoMap, Timing};
+    use portmidi::{Error, MidiEvent, MidiMessage};
+    use std::time::Duration;
+
+    let mut tempo_map = TempoMap::new(480);
+    tempo_map.set_tempo(960, 250_000); // 240 bpm after 1 second
+    assert_eq!(tempo_map.to_tick(Duration::from_millis(500)), 480);
+    assert_eq!(tempo_map.to_tick(Duration::from_millis(1250)), 1440);
+    assert_eq!(tempo_map.to_duration(1440), Duration::from_millis(1250));
+
+    let note = |timestamp, velocity| MidiEvent {
+        message: MidiMessage::from([0x90, 60, velocity, 0]),
+        timestamp,
+    };
+    let mut writer = SmfWriter::new(tempo_map);
+    writer.time_signature(3, 2);
+    let track = writer.add_track(Some("Piano"));
+    writer.add_midi_events(track, &[note(0, 100), note(1250, 0)]).unwrap();
+    writer.add_sysex(track, 500, &[0xF0, 0x7D, 0xF7]).unwrap();
+
+    let file = Smf::parse(&writer.to_smf(Format::Parallel).unwrap().to_bytes().unwrap()).unwrap();
+    assert_eq!(file.format, Format::Parallel);
+    assert_eq!(file.timing, Timing::Metrical(480));
+    assert_eq!(file.tracks.len(), 2);
+    let conductor = smf::absolute_ticks(&file.tracks[0]);
+    assert_eq!(conductor[2], (960, &EventKind::Meta(MetaEvent::Tempo(250_000))));
+    let events = smf::absolute_ticks(&file.tracks[1]);
+    assert_eq!(events[0].1, &EventKind::Meta(MetaEvent::TrackName("Piano".to_owned())));
+    assert_eq!(events[2], (480, &EventKind::SysEx(vec![0xF0, 0x7D, 0xF7])));
+    assert_eq!(events[3].0, 1440);
+    assert_eq!(events[3].1.midi_message(), Some(MidiMessage::from([0x90, 60, 0, 0])));
+    assert_eq!(events[4].1, &EventKind::Meta(MetaEvent::EndOfTrack));
+
+    let file = writer.to_smf(Format::SingleTrack).unwrap();
+    assert_eq!(Smf::parse(&file.to_bytes().unwrap()).unwrap(), file);
+    assert_eq!(file.tracks.len(), 1);
+
+    // deltas that don't fit in 28 bits are split
+    let mut writer = SmfWriter::new(TempoMap::new(480));
+    let track = writer.add_track(None);
+    writer.add_midi_events(track, &[note(0, 100), note(600_000_000, 0)]).unwrap();
+    let file = Smf::parse(&writer.to_smf(Format::Parallel).unwrap().to_bytes().unwrap()).unwrap();
+    let events = smf::absolute_ticks(&file.tracks[1]);
+    let last = events.iter().filter(|&&(_, kind)| kind.midi_message().is_some()).last().unwrap();
+    assert_eq!(last.0, 576_000_000);
+    let long = Smf {
+        format: Format::SingleTrack,
+        timing: Timing::Metrical(480),
+        tracks: vec![vec![
+            smf::TrackEvent { delta: 0x2000_0000, kind: EventKind::Meta(MetaEvent::EndOfTrack) },
+        ]],
+    };
+    let events = Smf::parse(&long.to_bytes().unwrap()).unwrap().tracks.remove(0);
+    assert_eq!(events.iter().map(|event| u64::from(event.delta)).sum::<u64>(), 0x2000_0000);
+
+    // values that don't fit in the file are rejected
+    let invalid = Smf { timing: Timing::Metrical(0x8000), ..long.clone() };
+    assert_eq!(invalid.to_bytes(), Err(Error::InvalidSmf("too many ticks per quarter note")));
+    let invalid = Smf { tracks: vec![Vec::new(); 0x10000], ..long };
+    assert_eq!(invalid.to_bytes(), Err(Error::InvalidSmf("too many tracks")));
+}
+
+#[test]
+fn test_recorder() {
+    use portmidi::smf::{Format, Smf, TempoMap};
+    use portmidi::{InputEvent, MidiEvent, MidiMessage, MockBackend, PortMidi, Recorder};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("in");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let port = context.input_port(context.device(input).unwrap(), 16).unwrap();
+    let note = |timestamp| MidiEvent {
+        message: MidiMessage::from([0x90, 60, 100, 0]),
+        timestamp,
+    };
+
+    let now = Arc::new(AtomicU32::new(100));
+    let mut recorder = Recorder::new(Arc::new({
+        let now = now.clone();
+        move || now.load(Ordering::SeqCst)
+    }));
+    recorder.start();
+    backend.push_input(input, note(150)).unwrap();
+    now.store(200, Ordering::SeqCst);
+    recorder.pause();
+    backend.push_input(input, note(250)).unwrap();
+    now.store(300, Ordering::SeqCst);
+    recorder.start();
+    backend.push_input(input, note(320)).unwrap();
+    let sysex = MidiMessage::from([0xF0, 0x7D, 0xF7, 0]);
+    backend
+        .push_input(input, MidiEvent { message: sysex, timestamp: 330 })
+        .unwrap();
+    assert_eq!(recorder.record(&port).unwrap(), 3);
+    now.store(400, Ordering::SeqCst);
+    recorder.stop();
+    // received before stopping, read afterwards
+    backend.push_input(input, note(390)).unwrap();
+    backend.push_input(input, note(450)).unwrap();
+    assert_eq!(recorder.record(&port).unwrap(), 1);
+
+    let times = recorder.events().iter().map(|e| e.time).collect::<Vec<_>>();
+    assert_eq!(times, vec![50, 120, 130, 190]);
+    assert_eq!(recorder.events()[0].event, InputEvent::Midi(note(150)));
+
+    let mut log = Vec::new();
+    recorder.write_log(&mut log).unwrap();
+    let log = String::from_utf8(log).unwrap();
+    assert_eq!(log.lines().nth(1), Some("50\t150\t90 3C 64"));
+    assert_eq!(log.lines().nth(3), Some("130\t330\tF0 7D F7"));
+
+    let file = recorder.to_smf(TempoMap::new(1000), Format::SingleTrack).unwrap();
+    assert_eq!(Smf::parse(&file.to_bytes().unwrap()).unwrap(), file);
+
+    // a new take with a punch-in range
+    now.store(1000, Ordering::SeqCst);
+    recorder.add_punch_range(100..200);
+    recorder.start();
+    for &timestamp in &[1050, 1150, 1250] {
+        recorder.push(note(timestamp));
+    }
+    assert_eq!(recorder.events().len(), 1);
+    assert_eq!(recorder.events()[0].time, 150);
+}
+
+#[test]
+fn test_player() {
+    use portmidi::smf::{Format, SmfWriter, TempoMap};
+    use portmidi::{Error, MidiEvent, MidiMessage, MockBackend, Player, PortMidi};
+    use std::time::Duration;
+
+    let backend = MockBackend::new();
+    let output = backend.add_output("out");
+    let other = backend.add_output("other");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let mut port = context.output_port(context.device(output).unwrap(), 16).unwrap();
+    let mut other_port = context.output_port(context.device(other).unwrap(), 16).unwrap();
+    let message = |bytes: [u8; 3]| MidiMessage::from([bytes[0], bytes[1], bytes[2], 0]);
+    let event = |timestamp, bytes| MidiEvent {
+        message: message(bytes),
+        timestamp,
+    };
+    let messages = || {
+        backend
+            .take_output(output)
+            .unwrap()
+            .into_iter()
+            .map(|event| event.message)
+            .collect::<Vec<_>>()
+    };
+
+    // the last note is still sounding at the end
+    let events = [
+        event(0, [0x90, 60, 100]),
+        event(10, [0x80, 60, 0]),
+        event(20, [0x91, 62, 100]),
+    ];
+    let player = Player::from_events(&events);
+    assert_eq!(player.duration(), Duration::from_millis(20));
+    assert_eq!(player.tempo_map().to_duration(20), player.duration());
+    player.play(&mut port).unwrap();
+    assert_eq!(
+        messages(),
+        vec![
+            message([0x90, 60, 100]),
+            message([0x80, 60, 0]),
+            message([0x91, 62, 100]),
+            message([0x81, 62, 0]),
+            message([0xB0, 123, 0]),
+            message([0xB1, 123, 0]),
+        ]
+    );
+    assert_eq!(player.position(), Duration::from_millis(0));
+
+    // split SysEx messages are sent as a whole, aborted ones are skipped
+    let events = [
+        MidiEvent { message: MidiMessage::from([0xF0, 0x7D, 1, 2]), timestamp: 0 },
+        event(1, [0xF8, 0, 0]),
+        MidiEvent { message: MidiMessage::from([3, 0xF7, 0, 0]), timestamp: 2 },
+        MidiEvent { message: MidiMessage::from([0xF0, 0x7D, 4, 5]), timestamp: 3 },
+        event(4, [0xB0, 7, 100]),
+    ];
+    Player::from_events(&events).play(&mut port).unwrap();
+    assert_eq!(
+        messages(),
+        vec![
+            MidiMessage::from([0xF0, 0x7D, 1, 2]),
+            MidiMessage::from([3, 0xF7, 0, 0]),
+            message([0xF8, 0, 0]),
+            message([0xB0, 7, 100]),
+            message([0xB0, 123, 0]),
+        ]
+    );
+
+    // loop the first note until stopped
+    player.set_loop(Some(Duration::from_millis(0)..Duration::from_millis(15)));
+    player.set_tempo_scale(2.0);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            assert!(player.is_playing());
+            assert_eq!(player.play(&mut other_port), Err(Error::AlreadyPlaying));
+            player.stop();
+        });
+        player.play(&mut port).unwrap();
+    });
+    let played = messages();
+    assert!(played.iter().filter(|&&msg| msg == message([0x90, 60, 100])).count() >= 4);
+    assert!(!played.contains(&message([0x91, 62, 100])));
+    assert_eq!(played.last(), Some(&message([0xB0, 123, 0])));
+
+    // a file with a tempo change
+    let mut tempo_map = TempoMap::new(480);
+    tempo_map.set_tempo(480, 250_000);
+    let mut writer = SmfWriter::new(tempo_map);
+    let track = writer.add_track(None);
+    writer.add_midi_events(track, &[event(750, [0x90, 64, 100])]).unwrap();
+    let player = Player::from_smf(&writer.to_smf(Format::Parallel).unwrap()).unwrap();
+    assert_eq!(player.tempo_map().to_tick(Duration::from_millis(750)), 960);
+    assert_eq!(player.duration(), Duration::from_millis(750));
+    player.seek(Duration::from_millis(740));
+    player.play(&mut port).unwrap();
+    assert_eq!(messages()[0], message([0x90, 64, 100]));
+
+    // system common and realtime messages are written as escaped bytes and played back
+    let mut writer = SmfWriter::new(TempoMap::new(480));
+    let track = writer.add_track(None);
+    let system = [event(0, [0xF2, 0x10, 0x01]), event(1, [0xF8, 0, 0])];
+    writer.add_midi_events(track, &system).unwrap();
+    let player = Player::from_smf(&writer.to_smf(Format::Parallel).unwrap()).unwrap();
+    player.play(&mut port).unwrap();
+    assert_eq!(messages(), vec![message([0xF2, 0x10, 0x01]), message([0xF8, 0, 0])]);
+}
+
+#[test]
+fn test_scheduler() {
+    use portmidi::{Error, MidiMessage, MockBackend, PortMidi, Scheduler};
+    use std::time::{Duration, Instant};
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("in");
+    let output = backend.add_output("out");
+    let context = Arc::new(PortMidi::with_backend(backend.clone()).unwrap());
+    let input = context.device(input).unwrap();
+    let output = context.device(output).unwrap();
+    assert_eq!(
+        Scheduler::new(context.clone(), input, 16).err(),
+        Some(Error::NotAnOutputDevice)
+    );
+
+    let scheduler = Scheduler::new(context.clone(), output.clone(), 16).unwrap();
+    assert!(scheduler.is_running());
+    let note = |note| MidiMessage::from([0x90, note, 100, 0]);
+    let start = Instant::now();
+    scheduler.schedule(start + Duration::from_millis(30), note(62));
+    scheduler.schedule(start + Duration::from_millis(10), note(60));
+    scheduler.schedule(start + Duration::from_millis(30), note(64));
+    scheduler
+        .schedule_sysex(start + Duration::from_millis(20), &[0xF0, 0x7D, 0xF7])
+        .unwrap();
+    assert_eq!(
+        scheduler.schedule_sysex(start, &[0xF0, 0x7D]),
+        Err(Error::MissingEox)
+    );
+    // the jitter is recorded after the messages were written
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while scheduler.jitter().count < 4 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(scheduler.pending(), 0);
+    let written = backend
+        .take_output(output.id())
+        .unwrap()
+        .into_iter()
+        .map(|event| event.message)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        written,
+        vec![
+            note(60),
+            MidiMessage::from([0xF0, 0x7D, 0xF7, 0]),
+            note(62),
+            note(64)
+        ]
+    );
+    let jitter = scheduler.jitter();
+    assert_eq!(jitter.count, 4);
+    assert!(jitter.min <= jitter.mean() && jitter.mean() <= jitter.max);
+    scheduler.reset_jitter();
+    assert_eq!(scheduler.jitter().count, 0);
+
+    // pending messages are discarded
+    scheduler.schedule(Instant::now() + Duration::from_secs(60), note(60));
+    assert_eq!(scheduler.pending(), 1);
+    scheduler.stop().unwrap();
+    assert!(backend.take_output(output.id()).unwrap().is_empty());
+    assert!(!backend.is_open(output.id()));
+}
+
+#[test]
+fn test_read_timeout() {
+    use portmidi::{MidiMessage, MockBackend, PortMidi};
+    use std::time::{Duration, Instant};
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("in");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let mut port = context.input_port(context.device(input).unwrap(), 16).unwrap();
+
+    let start = Instant::now();
+    assert_eq!(port.read_timeout(Duration::from_millis(20)), Ok(None));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert_eq!(port.read_timeout(Duration::from_millis(0)), Ok(None));
+
+    backend.push_input(input, MidiMessage::from([0x90, 60, 100, 0])).unwrap();
+    let event = port.read_timeout(Duration::from_millis(0)).unwrap().unwrap();
+    assert_eq!(event.message.data1, 60);
+
+    // data arriving from another thread ends the wait early
+    let writer = thread::spawn({
+        let backend = backend.clone();
+        move || {
+            thread::sleep(Duration::from_millis(10));
+            backend.push_input(input, MidiMessage::from([0x90, 62, 100, 0])).unwrap();
+        }
+    });
+    let start = Instant::now();
+    let event = port.read_timeout(Duration::from_secs(10)).unwrap().unwrap();
+    assert_eq!(event.message.data1, 62);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    writer.join().unwrap();
+
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        backend.push_input(input, MidiMessage::from([0x90, 64, 100, 0])).unwrap();
+    });
+    assert_eq!(port.read_blocking().unwrap().message.data1, 64);
+    writer.join().unwrap();
+}
+
+#[test]
+fn test_port_iterators() {
+    use portmidi::{MidiMessage, MockBackend, PortMidi};
+    use std::time::Duration;
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("in");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let mut port = context.input_port(context.device(input).unwrap(), 16).unwrap();
+    let note = |note| MidiMessage::from([0x90, note, 100, 0]);
+
+    assert_eq!(port.iter().count(), 0);
+    for n in 60..63 {
+        backend.push_input(input, note(n)).unwrap();
+    }
+    let notes = port.iter().map(|event| event.unwrap().message.data1).collect::<Vec<_>>();
+    assert_eq!(notes, vec![60, 61, 62]);
+    assert_eq!(port.iter().count(), 0);
+
+    // `events` blocks until all requested events were pushed
+    let writer = thread::spawn(move || {
+        for n in 64..67 {
+            thread::sleep(Duration::from_millis(5));
+            backend.push_input(input, note(n)).unwrap();
+        }
+    });
+    let notes = port
+        .events()
+        .take(3)
+        .map(|event| event.unwrap().message.data1)
+        .collect::<Vec<_>>();
+    assert_eq!(notes, vec![64, 65, 66]);
+    writer.join().unwrap();
+}
+
+/// Polls a future on the current thread until it is ready.
+#[cfg(feature = "async")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct Unparker(thread::Thread);
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(Unparker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_event_stream() {
+    use futures_core::Stream;
+    use portmidi::{MidiMessage, MockBackend, PortMidi};
+    use std::future;
+    use std::pin::Pin;
+    use std::time::{Duration, Instant};
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("in");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let mut stream = context
+        .input_port(context.device(input).unwrap(), 16)
+        .unwrap()
+        .into_stream();
+
+    backend.push_input(input, MidiMessage::from([0x90, 60, 100, 0])).unwrap();
+    let next = future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx));
+    assert_eq!(block_on(next).unwrap().unwrap().message.data1, 60);
+
+    // an idle stream is polled with an increasing interval
+    stream.set_max_poll_interval(Duration::from_millis(10));
+    let writer = thread::spawn({
+        let backend = backend.clone();
+        move || {
+            thread::sleep(Duration::from_millis(100));
+            backend.push_input(input, MidiMessage::from([0x90, 62, 100, 0])).unwrap();
+        }
+    });
+    let start = Instant::now();
+    let mut polls = 0;
+    let next = future::poll_fn(|cx| {
+        polls += 1;
+        Pin::new(&mut stream).poll_next(cx)
+    });
+    assert_eq!(block_on(next).unwrap().unwrap().message.data1, 62);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(polls < 30, "polled {} times", polls);
+    writer.join().unwrap();
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_event_sink() {
+    use futures_sink::Sink;
+    use portmidi::{MidiEvent, MidiMessage, MockBackend, PortMidi};
+    use std::future;
+    use std::pin::Pin;
+
+    let backend = MockBackend::new();
+    let output = backend.add_output("out");
+    let context = PortMidi::with_backend(backend.clone()).unwrap();
+    let mut sink = context
+        .output_port(context.device(output).unwrap(), 16)
+        .unwrap()
+        .into_sink();
+
+    let event = MidiEvent::from(MidiMessage::from([0x90, 60, 100, 0]));
+    block_on(future::poll_fn(|cx| Pin::new(&mut sink).poll_ready(cx))).unwrap();
+    Pin::new(&mut sink).start_send(event).unwrap();
+    block_on(future::poll_fn(|cx| Pin::new(&mut sink).poll_flush(cx))).unwrap();
+    assert_eq!(backend.take_output(output).unwrap(), vec![event]);
+    block_on(future::poll_fn(|cx| Pin::new(&mut sink).poll_close(cx))).unwrap();
+}
+
+#[test]
+fn test_listener() {
+    use portmidi::{Error, MidiMessage, MockBackend, PortMidi};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let backend = MockBackend::new();
+    let input = backend.add_input("in");
+    let output = backend.add_output("out");
+    let context = Arc::new(PortMidi::with_backend(backend.clone()).unwrap());
+    let out_device = context.device(output).unwrap();
+    assert_eq!(
+        context.clone().listen(out_device, 16, |_| ()).err(),
+        Some(Error::NotAnInputDevice)
+    );
+
+    let (tx, rx) = mpsc::channel();
+    let listener = context
+        .clone()
+        .listen(context.device(input).unwrap(), 16, move |event| {
+            tx.send(event.message.data1).unwrap();
+        })
+        .unwrap();
+    assert!(listener.is_running());
+    assert!(backend.is_open(input));
+    for note in 60..63 {
+        backend.push_input(input, MidiMessage::from([0x90, note, 100, 0])).unwrap();
+    }
+    let timeout = Duration::from_secs(10);
+    let notes = (0..3).map(|_| rx.recv_timeout(timeout).unwrap()).collect::<Vec<_>>();
+    assert_eq!(notes, vec![60, 61, 62]);
+
+    // dropping the handle joins the thread, which closes the port and the callback
+    drop(listener);
+    assert!(!backend.is_open(input));
+    assert_eq!(rx.recv_timeout(timeout), Err(mpsc::RecvTimeoutError::Disconnected));
+}